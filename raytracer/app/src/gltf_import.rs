@@ -0,0 +1,352 @@
+//! Imports glTF 2.0 files (`.gltf` and `.glb`) as scenes.
+//!
+//! Every mesh becomes a group of triangles and every node that uses it an instance of that group,
+//! so meshes used several times are only stored once. The renderer's camera sits at the origin
+//! looking down -Z, so the inverse of the camera node's transform is baked into every instance
+//! and light.
+
+use gltf;
+use gltf::camera::Projection;
+use gltf::image::Format;
+use gltf::khr_lights_punctual::Kind;
+use gltf::material::AlphaMode;
+use image::{DynamicImage, ImageBuffer};
+use raytracer::background::Background;
+use raytracer::camera::Projection as CameraProjection;
+use raytracer::instance::{Group, Instance, Transform, TransformStep};
+use raytracer::matrix::Matrix44;
+use raytracer::media::default_volume_steps;
+use raytracer::point::Point;
+use raytracer::scene::*;
+use raytracer::vector::Vector3;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Aspect ratio used when the file has no camera, or its camera does not specify one.
+const DEFAULT_ASPECT_RATIO: f64 = 16.0 / 9.0;
+
+/// Vertical field of view used when the file has no camera, in degrees.
+const DEFAULT_FOV: f64 = 60.0;
+
+pub fn is_gltf(path: &str) -> bool {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str());
+    extension.is_some_and(|e| e.eq_ignore_ascii_case("gltf") || e.eq_ignore_ascii_case("glb"))
+}
+
+/// Loads the default scene of a glTF file, as seen from its first camera, rendered `width`
+/// pixels wide.
+pub fn import(path: &str, width: u32) -> Result<Scene, String> {
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|e| format!("Unable to import {}: {}", path, e))?;
+    let textures: Vec<Option<Texture>> = images.into_iter()
+        .enumerate()
+        .map(|(i, data)| convert_image(data, PathBuf::from(format!("{}#image{}", path, i))))
+        .collect();
+
+    let gltf_scene = document.default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| format!("{} does not contain a scene", path))?;
+
+    let mut nodes = vec![];
+    for node in gltf_scene.nodes() {
+        collect_nodes(node, Matrix44::identity(), &mut nodes);
+    }
+
+    // The first camera decides the view; without one, the scene is seen from the origin.
+    let mut fov = DEFAULT_FOV;
+    let mut aspect_ratio = DEFAULT_ASPECT_RATIO;
+    let mut projection = CameraProjection::Perspective;
+    let mut view = Matrix44::identity();
+    if let Some(&(ref node, world)) = nodes.iter().find(|n| n.0.camera().is_some()) {
+        match node.camera().unwrap().projection() {
+            Projection::Perspective(perspective) => {
+                fov = (perspective.yfov() as f64).to_degrees();
+                if let Some(ratio) = perspective.aspect_ratio() {
+                    aspect_ratio = ratio as f64;
+                }
+            }
+            Projection::Orthographic(orthographic) => {
+                let (xmag, ymag) = (orthographic.xmag() as f64, orthographic.ymag() as f64);
+                projection = CameraProjection::Orthographic { height: 2.0 * ymag };
+                aspect_ratio = xmag / ymag;
+            }
+        }
+        view = world.inverse();
+    }
+
+    let mut groups = HashMap::new();
+    let mut elements = vec![];
+    let mut lights = vec![];
+    for &(ref node, world) in &nodes {
+        let transform = world * view;
+        if let Some(mesh) = node.mesh() {
+            let name = format!("mesh{}", mesh.index());
+            if !groups.contains_key(&name) {
+                groups.insert(name.clone(), convert_mesh(&mesh, &buffers, &textures)?);
+            }
+            elements.push(Element::Instance(Instance {
+                group: name,
+                transform: Transform::new(vec![TransformStep::Matrix(to_elements(&transform))]),
+            }));
+        }
+        if let Some(light) = node.light() {
+            lights.push(convert_light(&light, &transform));
+        }
+    }
+
+    Ok(Scene {
+        width,
+        height: (width as f64 / aspect_ratio).round() as u32,
+        fov,
+        projection,
+        stereo: None,
+        elements,
+        lights,
+        background: Background::default(),
+        fog: None,
+        volumes: vec![],
+        volume_steps: default_volume_steps(),
+        spectral: None,
+        caustics: None,
+        adaptive_sampling: None,
+        light_sampling: None,
+        groups,
+        shadow_bias: 1e-4,
+        max_recursion_depth: 8,
+        cache: SceneCache::default(),
+    })
+}
+
+/// Lists the node and its descendants along with their object-to-world transforms.
+fn collect_nodes<'a>(node: gltf::Node<'a>,
+                     parent: Matrix44,
+                     nodes: &mut Vec<(gltf::Node<'a>, Matrix44)>) {
+    let world = convert_matrix(node.transform().matrix()) * parent;
+    for child in node.children() {
+        collect_nodes(child, world, nodes);
+    }
+    nodes.push((node, world));
+}
+
+/// glTF matrices are column-major and act on column vectors, so their columns are the rows of the
+/// equivalent row-vector `Matrix44`.
+fn convert_matrix(columns: [[f32; 4]; 4]) -> Matrix44 {
+    let mut elements = [[0.0; 4]; 4];
+    for i in 0..4 {
+        for j in 0..4 {
+            elements[i][j] = columns[i][j] as f64;
+        }
+    }
+    Matrix44::new(elements)
+}
+
+fn to_elements(matrix: &Matrix44) -> [[f64; 4]; 4] {
+    [matrix[0], matrix[1], matrix[2], matrix[3]]
+}
+
+fn convert_image(data: gltf::image::Data, path: PathBuf) -> Option<Texture> {
+    let (width, height, pixels) = (data.width, data.height, data.pixels);
+    let image = match data.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8)
+        }
+        Format::R8G8B8 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+        }
+        // High dynamic range images are not supported by the texture lookup.
+        _ => None,
+    };
+    image.map(|image| {
+        Texture {
+            path,
+            texture: Arc::new(image),
+        }
+    })
+}
+
+/// Maps a metallic-roughness material onto the raytracer's material model. Transmissive
+/// materials become refractive, smooth metals become reflective in proportion to how metallic and
+/// smooth they are, and everything else is diffuse.
+///
+/// Blended materials become partly opaque, and masked ones are cut out where the alpha of their
+/// texture is below one half, whatever their own cutoff.
+fn convert_material(material: &gltf::Material, textures: &[Option<Texture>]) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [red, green, blue, alpha] = pbr.base_color_factor();
+    let base_color = Color { red, green, blue };
+    let alpha_mode = material.alpha_mode();
+    let coloration = pbr.base_color_texture()
+        .and_then(|info| textures[info.texture().source().index()].clone())
+        .map(|texture| {
+            let keep_alpha = alpha_mode != AlphaMode::Opaque && texture.has_alpha();
+            if red == 1.0 && green == 1.0 && blue == 1.0 && keep_alpha == texture.has_alpha() {
+                texture
+            } else {
+                tinted_texture(&texture, base_color, keep_alpha)
+            }
+        })
+        .map_or(Coloration::Color(base_color), Coloration::Texture);
+
+    let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+    let reflectivity = pbr.metallic_factor() * (1.0 - pbr.roughness_factor());
+    let surface = if transmission > 0.0 {
+        SurfaceType::Refractive {
+            index: material.ior().unwrap_or(1.5),
+            transparency: transmission,
+            dispersion: None,
+        }
+    } else if reflectivity > 0.0 {
+        SurfaceType::Reflective { reflectivity }
+    } else {
+        SurfaceType::Diffuse
+    };
+
+    let [red, green, blue] = material.emissive_factor();
+    let emission = if red > 0.0 || green > 0.0 || blue > 0.0 {
+        Some(Emission {
+            color: Color { red, green, blue },
+            strength: material.emissive_strength().unwrap_or(1.0),
+        })
+    } else {
+        None
+    };
+
+    Material {
+        coloration,
+        // The base color is the reflectance itself.
+        albedo: 1.0,
+        surface,
+        emission,
+        opacity: if alpha_mode == AlphaMode::Blend { alpha } else { 1.0 },
+    }
+}
+
+/// A copy of the texture multiplied by the base color factor, keeping its alpha channel only for
+/// materials that use it.
+fn tinted_texture(texture: &Texture, tint: Color, keep_alpha: bool) -> Texture {
+    let mut pixels = texture.texture.to_rgba();
+    for pixel in pixels.pixels_mut() {
+        let tinted = (Color::from_rgba(*pixel) * tint).to_rgba();
+        pixel.data = [tinted.data[0], tinted.data[1], tinted.data[2], pixel.data[3]];
+    }
+    let image = DynamicImage::ImageRgba8(pixels);
+    Texture {
+        path: texture.path.clone(),
+        texture: Arc::new(if keep_alpha {
+            image
+        } else {
+            DynamicImage::ImageRgb8(image.to_rgb())
+        }),
+    }
+}
+
+/// Builds a group holding the triangles of every primitive of the mesh, in the mesh's own space.
+fn convert_mesh(mesh: &gltf::Mesh,
+                buffers: &[gltf::buffer::Data],
+                textures: &[Option<Texture>])
+                -> Result<Group, String> {
+    let mut triangles = vec![];
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            eprintln!("warning: skipping a primitive of mesh {} that is not made of triangles",
+                      mesh.index());
+            continue;
+        }
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<Point> = match reader.read_positions() {
+            Some(positions) => {
+                positions.map(|[x, y, z]| {
+                        Point {
+                            x: x as f64,
+                            y: y as f64,
+                            z: z as f64,
+                        }
+                    })
+                    .collect()
+            }
+            None => continue,
+        };
+        let normals: Option<Vec<Vector3>> = reader.read_normals().map(|normals| {
+            normals.map(|[x, y, z]| {
+                    Vector3 {
+                        x: x as f64,
+                        y: y as f64,
+                        z: z as f64,
+                    }
+                })
+                .collect()
+        });
+        let tex_coords: Option<Vec<[f32; 2]>> =
+            reader.read_tex_coords(0).map(|t| t.into_f32().collect());
+        let indices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        let vertices = positions.len();
+        if normals.as_ref().is_some_and(|n| n.len() != vertices) ||
+           tex_coords.as_ref().is_some_and(|t| t.len() != vertices) {
+            return Err(format!("Mesh {} has attributes of different lengths", mesh.index()));
+        }
+        if let Some(&index) = indices.iter().find(|&&i| i >= vertices) {
+            return Err(format!("Mesh {} refers to vertex {} of {}", mesh.index(), index, vertices));
+        }
+
+        let material = convert_material(&primitive.material(), textures);
+        for corners in indices.chunks(3).filter(|c| c.len() == 3) {
+            let [a, b, c] = [corners[0], corners[1], corners[2]];
+            triangles.push(Element::Triangle(Triangle {
+                vertices: [positions[a], positions[b], positions[c]],
+                normals: normals.as_ref().map(|n| [n[a], n[b], n[c]]),
+                tex_coords: tex_coords.as_ref().map(|t| [t[a], t[b], t[c]]),
+                material: material.clone(),
+            }));
+        }
+    }
+    Ok(Group::new(triangles))
+}
+
+/// Converts a punctual light. glTF gives point and spot intensities in candela, which the
+/// raytracer's lights express as the total power over the sphere, hence the factor of 4π.
+fn convert_light(light: &gltf::khr_lights_punctual::Light, transform: &Matrix44) -> Light {
+    let [red, green, blue] = light.color();
+    let color = Color { red, green, blue };
+    // Lights shine down their local -Z axis.
+    let forward = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+    };
+    let direction = (*transform * forward).normalize();
+    let position = Point::zero() * *transform;
+    match light.kind() {
+        Kind::Directional => {
+            Light::Directional(DirectionalLight {
+                direction,
+                color,
+                intensity: light.intensity(),
+            })
+        }
+        Kind::Point => {
+            Light::Spherical(SphericalLight {
+                position,
+                color,
+                intensity: 4.0 * PI * light.intensity(),
+            })
+        }
+        Kind::Spot { inner_cone_angle, outer_cone_angle } => {
+            Light::Spot(SpotLight {
+                position,
+                direction,
+                color,
+                intensity: 4.0 * PI * light.intensity(),
+                inner_angle: (inner_cone_angle as f64).to_degrees(),
+                outer_angle: (outer_cone_angle as f64).to_degrees(),
+                profile: None,
+            })
+        }
+    }
+}
@@ -1,13 +1,112 @@
 extern crate clap;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 extern crate raytracer;
 extern crate image;
+extern crate gltf;
+extern crate tiny_http;
 
-use clap::{Arg, App};
+mod gltf_import;
+mod serve;
+
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
 use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use raytracer::scene::*;
-use image::ImageFormat;
+use raytracer::progress::Progress;
+use raytracer::checkpoint::Checkpoint;
+use raytracer::denoise::DenoiseSettings;
+use raytracer::stats::RenderStats;
+use image::{DynamicImage, ImageFormat};
+
+/// Width of images rendered from glTF files, which do not specify a resolution.
+const DEFAULT_GLTF_WIDTH: u32 = 800;
+
+/// Reports an error and exits with a non-zero status.
+fn fail<M: std::fmt::Display>(message: M) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1)
+}
+
+/// Parses an optional argument, failing with `message` if it is present but invalid.
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str, message: &str) -> Option<T> {
+    matches.value_of(name).map(|s| s.parse().unwrap_or_else(|_| fail(message)))
+}
+
+/// Like `parse_arg`, also failing if the value is 0.
+fn parse_positive<T>(matches: &ArgMatches, name: &str, message: &str) -> Option<T>
+    where T: FromStr + Default + PartialEq
+{
+    let value = parse_arg(matches, name, message);
+    if value == Some(T::default()) {
+        fail(message);
+    }
+    value
+}
+
+fn save_image<P: AsRef<Path>>(image: &DynamicImage, image_path: P) {
+    let image_path = image_path.as_ref();
+    let mut image_file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(image_path)
+        .unwrap_or_else(|e| fail(format!("Unable to create {:?}: {}", image_path, e)));
+    image.save(&mut image_file, ImageFormat::PNG)
+        .unwrap_or_else(|e| fail(format!("Unable to write {:?}: {}", image_path, e)));
+}
+
+/// Builds the path of an extra output written next to the image, e.g. `out.depth.png`.
+fn sibling_path(image_path: &str, suffix: &str) -> PathBuf {
+    let path = Path::new(image_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}h{:02}m{:02}s", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+fn print_progress(progress: &Progress) {
+    let eta = progress.eta().map(format_duration).unwrap_or_else(|| "?".to_string());
+    println!("Rendered {}/{} tiles ({:.1}%), elapsed {}, ETA {}",
+             progress.completed_tiles,
+             progress.total_tiles,
+             progress.fraction() * 100.0,
+             format_duration(progress.elapsed),
+             eta);
+}
+
+fn run_server(matches: &ArgMatches) {
+    let address = matches.value_of("address").unwrap_or("127.0.0.1:8080");
+    let settings = serve::Settings {
+        max_renders: parse_positive(matches,
+                                    "max-renders",
+                                    "Maximum renders must be a positive integer")
+            .unwrap_or(1),
+        max_queued: parse_arg(matches, "max-queued", "Maximum queued jobs must be an integer")
+            .unwrap_or(16),
+        max_finished: parse_arg(matches, "max-finished", "Maximum finished jobs must be an integer")
+            .unwrap_or(64),
+        max_connections: parse_positive(matches,
+                                        "max-connections",
+                                        "Maximum connections must be a positive integer")
+            .unwrap_or(32),
+        asset_root: matches.value_of("assets").map(PathBuf::from),
+        tile_size: parse_positive(matches, "tile-size", "Tile size must be a positive integer")
+            .unwrap_or(raytracer::DEFAULT_TILE_SIZE),
+        max_body_size: parse_arg(matches, "max-body-size", "Maximum body size must be an integer")
+            .unwrap_or(16 * 1024 * 1024),
+        max_pixels: parse_arg(matches, "max-pixels", "Maximum pixels must be an integer")
+            .unwrap_or(4096 * 4096),
+    };
+    serve::serve(address, settings).unwrap_or_else(|e| fail(format!("Unable to serve: {}", e)));
+}
 
 fn main() {
     let app = App::new("raytracer")
@@ -15,21 +114,130 @@ fn main() {
         .author("bheisler <redattack34@gmail.com>")
         .about("Basic Raytracer")
         .arg(Arg::with_name("scene")
-            .help("Sets the scene file to use")
+            .help("Sets the scene file to use, either JSON or glTF (.gltf or .glb)")
             .required(true)
             .index(1))
         .arg(Arg::with_name("image")
             .help("Sets the output image file")
             .required(true)
-            .index(2));
+            .index(2))
+        .arg(Arg::with_name("width")
+            .long("width")
+            .value_name("PIXELS")
+            .help("Sets the width of the image rendered from a glTF file (defaults to 800)")
+            .takes_value(true))
+        .arg(Arg::with_name("tile-size")
+            .long("tile-size")
+            .value_name("PIXELS")
+            .help("Sets the width and height of the tiles the image is rendered in")
+            .takes_value(true))
+        .arg(Arg::with_name("preview-interval")
+            .long("preview-interval")
+            .value_name("SECONDS")
+            .help("Periodically writes the partially rendered image to the output file")
+            .takes_value(true))
+        .arg(Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .value_name("FILE")
+            .help("Periodically saves the completed tiles to this file so the render can be resumed")
+            .takes_value(true))
+        .arg(Arg::with_name("checkpoint-interval")
+            .long("checkpoint-interval")
+            .value_name("SECONDS")
+            .help("Sets how often the checkpoint is saved (defaults to 60 seconds)")
+            .takes_value(true)
+            .requires("checkpoint"))
+        .arg(Arg::with_name("resume")
+            .long("resume")
+            .help("Resumes the render from the checkpoint file")
+            .requires("checkpoint"))
+        .arg(Arg::with_name("aovs")
+            .long("aovs")
+            .help("Also writes depth, normal, albedo, element ID, direct and indirect passes next to the image"))
+        .arg(Arg::with_name("sample-heatmap")
+            .long("sample-heatmap")
+            .help("Also writes an image of the number of samples taken for each pixel next to the image"))
+        .arg(Arg::with_name("tile-heatmap")
+            .long("tile-heatmap")
+            .help("Also writes an image of the time taken to render each tile next to the image"))
+        .arg(Arg::with_name("denoise")
+            .long("denoise")
+            .help("Runs an edge-aware denoiser guided by the normal, albedo and depth passes"))
+        .arg(Arg::with_name("denoise-radius")
+            .long("denoise-radius")
+            .value_name("PIXELS")
+            .help("Sets the size of the denoising filter window")
+            .takes_value(true)
+            .requires("denoise"))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("serve")
+            .about("Renders JSON scenes posted to an HTTP server")
+            .arg(Arg::with_name("address")
+                .long("address")
+                .value_name("HOST:PORT")
+                .help("Sets the address to listen on (defaults to 127.0.0.1:8080)")
+                .takes_value(true))
+            .arg(Arg::with_name("max-renders")
+                .long("max-renders")
+                .value_name("COUNT")
+                .help("Sets how many scenes are rendered at the same time (defaults to 1)")
+                .takes_value(true))
+            .arg(Arg::with_name("max-queued")
+                .long("max-queued")
+                .value_name("COUNT")
+                .help("Sets how many jobs may wait to be rendered (defaults to 16)")
+                .takes_value(true))
+            .arg(Arg::with_name("max-finished")
+                .long("max-finished")
+                .value_name("COUNT")
+                .help("Sets how many finished jobs are kept until they are fetched (defaults to 64)")
+                .takes_value(true))
+            .arg(Arg::with_name("max-connections")
+                .long("max-connections")
+                .value_name("COUNT")
+                .help("Sets how many requests are handled at the same time (defaults to 32)")
+                .takes_value(true))
+            .arg(Arg::with_name("assets")
+                .long("assets")
+                .value_name("DIR")
+                .help("Sets the directory that textures and light profiles are loaded from; \
+                       without it, scenes may not refer to files")
+                .takes_value(true))
+            .arg(Arg::with_name("tile-size")
+                .long("tile-size")
+                .value_name("PIXELS")
+                .help("Sets the width and height of the tiles the images are rendered in")
+                .takes_value(true))
+            .arg(Arg::with_name("max-body-size")
+                .long("max-body-size")
+                .value_name("BYTES")
+                .help("Sets the size of the largest scene accepted (defaults to 16 MiB)")
+                .takes_value(true))
+            .arg(Arg::with_name("max-pixels")
+                .long("max-pixels")
+                .value_name("COUNT")
+                .help("Sets the number of pixels of the largest image rendered (defaults to 4096x4096)")
+                .takes_value(true)));
     let matches = app.get_matches();
 
-    let scene_path = matches.value_of("scene").unwrap();
-    let scene_file = File::open(scene_path).expect("File not found");
+    if let Some(matches) = matches.subcommand_matches("serve") {
+        run_server(matches);
+        return;
+    }
 
+    let scene_path = matches.value_of("scene").unwrap();
     let image_path = matches.value_of("image").unwrap();
 
-    let scene: Scene = serde_json::from_reader(scene_file).unwrap();
+    let scene: Scene = if gltf_import::is_gltf(scene_path) {
+        let width = parse_positive(&matches, "width", "Width must be a positive integer")
+            .unwrap_or(DEFAULT_GLTF_WIDTH);
+        gltf_import::import(scene_path, width).unwrap_or_else(|e| fail(e))
+    } else {
+        let scene_file = File::open(scene_path)
+            .unwrap_or_else(|e| fail(format!("Unable to open {}: {}", scene_path, e)));
+        serde_json::from_reader(scene_file)
+            .unwrap_or_else(|e| fail(format!("Unable to parse {}: {}", scene_path, e)))
+    };
 
     let block = raytracer::ViewBlock {
         x: 0,
@@ -40,11 +248,102 @@ fn main() {
 
     println!("Start Rendering !");
 
-    let image = raytracer::render(&block, &scene);
+    let tile_size: Option<u32> =
+        parse_positive(&matches, "tile-size", "Tile size must be a positive integer");
+    let preview_interval =
+        parse_arg(&matches, "preview-interval", "Preview interval must be a number of seconds")
+            .map(Duration::from_secs);
+
+    let checkpoint_path = matches.value_of("checkpoint");
+    let checkpoint_interval = Duration::from_secs(
+        parse_arg(&matches, "checkpoint-interval", "Checkpoint interval must be a number of seconds")
+            .unwrap_or(60));
+
+    let mut checkpoint = if matches.is_present("resume") {
+        let path = checkpoint_path.unwrap_or_else(|| fail("--resume requires --checkpoint"));
+        let checkpoint = Checkpoint::load(path)
+            .unwrap_or_else(|e| fail(format!("Unable to load checkpoint: {}", e)));
+        let tile_size = tile_size.unwrap_or(checkpoint.tile_size);
+        let compatible = checkpoint.is_compatible(&block, tile_size, &scene)
+            .unwrap_or_else(|e| fail(format!("Unable to check the checkpoint: {}", e)));
+        if !compatible {
+            fail("Checkpoint was taken from a render of a different scene, size or tile size");
+        }
+        println!("Resuming from {} completed tiles", checkpoint.tiles().len());
+        checkpoint
+    } else {
+        Checkpoint::new(&block, tile_size.unwrap_or(raytracer::DEFAULT_TILE_SIZE), &scene)
+            .unwrap_or_else(|e| fail(format!("Unable to start a checkpoint: {}", e)))
+    };
+
+    let mut last_preview = Instant::now();
+    let mut last_checkpoint = Instant::now();
+    let mut stats = RenderStats::default();
+    let result = raytracer::render_resumable(&block, &scene, &mut checkpoint, |progress, image, checkpoint| {
+        print_progress(progress);
+        stats.add(&progress.stats);
+        if let Some(interval) = preview_interval {
+            if !progress.is_done() && last_preview.elapsed() >= interval {
+                save_image(image, image_path);
+                last_preview = Instant::now();
+            }
+        }
+        if let Some(path) = checkpoint_path {
+            if progress.is_done() || last_checkpoint.elapsed() >= checkpoint_interval {
+                checkpoint.save(path)
+                    .unwrap_or_else(|e| fail(format!("Unable to save checkpoint: {}", e)));
+                last_checkpoint = Instant::now();
+            }
+        }
+    });
+    let image = result.unwrap_or_else(|e| fail(format!("Unable to render the scene: {}", e)));
 
     println!("End Rendering !");
+    println!("{}", stats);
+
+    if matches.is_present("tile-heatmap") {
+        save_image(&stats.tile_heatmap(&block), sibling_path(image_path, "tiles"));
+    }
+
+    if matches.is_present("sample-heatmap") {
+        let heatmap = raytracer::sampling::heatmap_image(block.width,
+                                                         block.height,
+                                                         &checkpoint.samples());
+        save_image(&heatmap, sibling_path(image_path, "samples"));
+    }
+
+    if !matches.is_present("aovs") && !matches.is_present("denoise") {
+        save_image(&image, image_path);
+        return;
+    }
+
+    println!("Rendering AOVs");
+    let beauty = checkpoint.colors();
+    // With adaptive sampling, the AOVs take the same samples as the beauty pass.
+    let aovs = raytracer::aov::render_aovs_with_samples(&block, &scene, &checkpoint.samples())
+        .unwrap_or_else(|e| fail(format!("Unable to render the AOVs: {}", e)));
+
+    if matches.is_present("denoise") {
+        println!("Denoising");
+        let mut settings = DenoiseSettings::default();
+        if let Some(radius) =
+            parse_arg(&matches, "denoise-radius", "Denoise radius must be a positive integer") {
+            settings.radius = radius;
+        }
+        let denoised = raytracer::denoise::denoise(&beauty, &aovs, &settings)
+            .unwrap_or_else(|e| fail(format!("Unable to denoise the image: {}", e)));
+        save_image(&raytracer::colors_to_image(block.width, block.height, &denoised),
+                   image_path);
+    } else {
+        save_image(&image, image_path);
+    }
 
-    let mut image_file =
-        OpenOptions::new().write(true).truncate(true).create(true).open(image_path).unwrap();
-    image.save(&mut image_file, ImageFormat::PNG).unwrap();
+    if matches.is_present("aovs") {
+        save_image(&aovs.depth_image(), sibling_path(image_path, "depth"));
+        save_image(&aovs.normal_image(), sibling_path(image_path, "normal"));
+        save_image(&aovs.albedo_image(), sibling_path(image_path, "albedo"));
+        save_image(&aovs.element_image(), sibling_path(image_path, "id"));
+        save_image(&aovs.direct_image(), sibling_path(image_path, "direct"));
+        save_image(&aovs.indirect_image(), sibling_path(image_path, "indirect"));
+    }
 }
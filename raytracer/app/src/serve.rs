@@ -0,0 +1,439 @@
+//! HTTP render service.
+//!
+//! Scenes are posted as JSON and rendered by a fixed number of worker threads, so that no more
+//! than that many renders run at once; further jobs wait in a bounded queue. Requests are handled
+//! by a fixed number of threads as well.
+//!
+//! Files that scenes refer to, such as textures, environment maps and light profiles, are
+//! resolved under the asset directory of the server. Scenes may not leave it through absolute
+//! paths or `..`, and are refused if they refer to files when the server has no asset directory.
+//!
+//! * `POST /render` renders the scene in the body and responds with the PNG once it is done.
+//! * `POST /jobs` queues the scene in the body and responds with the ID of the job.
+//! * `GET /jobs/{id}` reports the status of a job.
+//! * `GET /jobs/{id}/image` responds with the PNG of a finished job.
+//! * `DELETE /jobs/{id}` discards a job that is queued or finished. Finished jobs are kept until
+//!   they are deleted, or until too many others have finished after them.
+//!
+//! Both `POST` endpoints accept `x`, `y`, `width` and `height` query parameters to render only a
+//! region of the image. They default to the rest of the image past `x` and `y`.
+//!
+//! Scenes larger than the body size limit are refused with 413, and scenes with more pixels than
+//! the server allows with 400, before anything is allocated for them.
+
+use raytracer::{self, ViewBlock};
+use raytracer::scene::Scene;
+use image::ImageFormat;
+use serde_json::{self, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+pub struct Settings {
+    /// Number of scenes rendered at the same time.
+    pub max_renders: usize,
+    /// Number of jobs that may wait for a render slot before new ones are refused.
+    pub max_queued: usize,
+    /// Number of finished jobs kept for their clients; older ones are discarded first.
+    pub max_finished: usize,
+    /// Number of requests handled at the same time. Requests to `/render` hold on to theirs
+    /// until the render is done.
+    pub max_connections: usize,
+    /// Directory that the files scenes refer to are resolved under, if they may refer to any.
+    pub asset_root: Option<PathBuf>,
+    pub tile_size: u32,
+    /// Largest request body accepted, in bytes.
+    pub max_body_size: u64,
+    /// Largest image accepted, in pixels.
+    pub max_pixels: u64,
+}
+
+enum Status {
+    Queued,
+    Rendering { progress: f64 },
+    Done { png: Vec<u8> },
+    Failed { error: String },
+}
+
+struct Job {
+    status: Status,
+    /// Jobs posted to `/render` are discarded as soon as their response is sent.
+    synchronous: bool,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: HashMap<u64, Job>,
+    queue: VecDeque<(u64, Scene, ViewBlock)>,
+    /// Finished jobs posted to `/jobs`, oldest first.
+    finished: VecDeque<u64>,
+    next_id: u64,
+}
+
+#[derive(Default)]
+struct Service {
+    state: Mutex<State>,
+    /// Signalled when a job is queued.
+    queued: Condvar,
+    /// Signalled when a job is done or has failed.
+    finished: Condvar,
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+pub fn serve(address: &str, settings: Settings) -> Result<(), String> {
+    let asset_root = match settings.asset_root {
+        Some(ref root) => {
+            Some(root.canonicalize()
+                .map_err(|e| format!("Unable to open the asset directory {:?}: {}", root, e))?)
+        }
+        None => None,
+    };
+    let settings = Arc::new(Settings { asset_root, ..settings });
+    let server = Arc::new(Server::http(address).map_err(|e| e.to_string())?);
+    let service = Arc::new(Service::default());
+    for _ in 0..settings.max_renders {
+        let service = service.clone();
+        let settings = settings.clone();
+        thread::spawn(move || work(&service, &settings));
+    }
+    println!("Listening on {}", server.server_addr());
+
+    let handlers: Vec<_> = (0..settings.max_connections.max(1))
+        .map(|_| {
+            let (server, service, settings) = (server.clone(), service.clone(), settings.clone());
+            thread::spawn(move || for request in server.incoming_requests() {
+                handle(&service, request, &settings);
+            })
+        })
+        .collect();
+    for handler in handlers {
+        handler.join().map_err(|_| "A request handler panicked".to_string())?;
+    }
+    Ok(())
+}
+
+fn work(service: &Service, settings: &Settings) {
+    loop {
+        let (id, scene, block) = {
+            let mut state = service.state.lock().unwrap();
+            loop {
+                if let Some(job) = state.queue.pop_front() {
+                    break job;
+                }
+                state = service.queued.wait(state).unwrap();
+            }
+        };
+        set_status(service, id, Status::Rendering { progress: 0.0 });
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            raytracer::render_with_progress(&block, &scene, settings.tile_size, |progress, _| {
+                set_status(service, id, Status::Rendering { progress: progress.fraction() });
+            })
+        }));
+        let status = match result {
+            Ok(Ok(image)) => {
+                let mut png = Vec::new();
+                match image.save(&mut png, ImageFormat::PNG) {
+                    Ok(()) => Status::Done { png },
+                    Err(e) => {
+                        Status::Failed { error: format!("Unable to encode the image: {}", e) }
+                    }
+                }
+            }
+            Ok(Err(e)) => Status::Failed { error: e.to_string() },
+            Err(_) => Status::Failed { error: "The renderer panicked".to_string() },
+        };
+        set_status(service, id, status);
+        retire(service, id, settings.max_finished);
+        service.finished.notify_all();
+    }
+}
+
+/// Records that a job has finished, discarding the oldest finished jobs beyond `max_finished`.
+/// Jobs posted to `/render` are discarded by their own request instead.
+fn retire(service: &Service, id: u64, max_finished: usize) {
+    let mut state = service.state.lock().unwrap();
+    if state.jobs.get(&id).is_none_or(|job| job.synchronous) {
+        return;
+    }
+    state.finished.push_back(id);
+    while state.finished.len() > max_finished {
+        let oldest = state.finished.pop_front().unwrap();
+        state.jobs.remove(&oldest);
+    }
+}
+
+/// Updates the status of a job, unless it was deleted in the meantime.
+fn set_status(service: &Service, id: u64, status: Status) {
+    if let Some(job) = service.state.lock().unwrap().jobs.get_mut(&id) {
+        job.status = status;
+    }
+}
+
+fn handle(service: &Service, mut request: Request, settings: &Settings) {
+    let url = request.url().to_string();
+    let (path, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => (&url[..], ""),
+    };
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let method = request.method().clone();
+    let response = match (&method, &segments[..]) {
+        (&Method::Post, ["render"]) => {
+            match submit(service, &mut request, query, settings, true) {
+                Ok(id) => wait(service, id),
+                Err(response) => response,
+            }
+        }
+        (&Method::Post, ["jobs"]) => {
+            match submit(service, &mut request, query, settings, false) {
+                Ok(id) => {
+                    json_response(202, &json!({ "id": id }))
+                        .with_header(header("Location", &format!("/jobs/{}", id)))
+                }
+                Err(response) => response,
+            }
+        }
+        (method, ["jobs", id]) | (method, ["jobs", id, "image"]) => {
+            match id.parse() {
+                Ok(id) => {
+                    match (method, segments.len()) {
+                        (&Method::Get, 2) => job_status(service, id),
+                        (&Method::Get, _) => job_image(service, id),
+                        (&Method::Delete, 2) => delete_job(service, id),
+                        _ => error_response(405, "Method not allowed"),
+                    }
+                }
+                Err(_) => error_response(404, "No such job"),
+            }
+        }
+        _ => error_response(404, "Not found"),
+    };
+    // The client may have gone away, in which case there is nobody left to tell.
+    let _ = request.respond(response);
+}
+
+/// Parses the scene and region of a request and queues the job, returning its ID.
+fn submit(service: &Service,
+          request: &mut Request,
+          query: &str,
+          settings: &Settings,
+          synchronous: bool)
+          -> Result<u64, HttpResponse> {
+    let queue_full = || error_response(503, "Too many jobs are queued");
+    // Refuse the job before reading the scene if it could not be queued anyway.
+    if service.state.lock().unwrap().queue.len() >= settings.max_queued {
+        return Err(queue_full());
+    }
+    let too_large = || error_response(413, "The scene is too large");
+    if request.body_length().is_some_and(|length| length as u64 > settings.max_body_size) {
+        return Err(too_large());
+    }
+    // The length may not be given up front, so the body is cut off just past the limit as well.
+    let mut body = vec![];
+    request.as_reader()
+        .take(settings.max_body_size + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| error_response(400, &format!("Unable to read the scene: {}", e)))?;
+    if body.len() as u64 > settings.max_body_size {
+        return Err(too_large());
+    }
+    let mut value: Value = serde_json::from_slice(&body)
+        .map_err(|e| error_response(400, &format!("Invalid scene: {}", e)))?;
+    resolve_files(&mut value, settings.asset_root.as_deref())
+        .map_err(|e| error_response(400, &e))?;
+    let scene: Scene = serde_json::from_value(value)
+        .map_err(|e| error_response(400, &format!("Invalid scene: {}", e)))?;
+    if scene.width as u64 * scene.height as u64 > settings.max_pixels {
+        let message = format!("Images of {}x{} pixels are larger than the {} pixels allowed",
+                              scene.width,
+                              scene.height,
+                              settings.max_pixels);
+        return Err(error_response(400, &message));
+    }
+    let block = parse_block(query, &scene).map_err(|e| error_response(400, &e))?;
+
+    let mut state = service.state.lock().unwrap();
+    if state.queue.len() >= settings.max_queued {
+        return Err(queue_full());
+    }
+    let id = state.next_id;
+    state.next_id += 1;
+    state.jobs.insert(id,
+                      Job {
+                          status: Status::Queued,
+                          synchronous,
+                      });
+    state.queue.push_back((id, scene, block));
+    service.queued.notify_one();
+    Ok(id)
+}
+
+/// Replaces the paths of the files a scene refers to, which are the `path` of its textures and
+/// light profiles, with their location under the asset root.
+fn resolve_files(value: &mut Value, asset_root: Option<&Path>) -> Result<(), String> {
+    match *value {
+        Value::Object(ref mut fields) => {
+            for (name, field) in fields.iter_mut() {
+                match *field {
+                    Value::String(ref mut path) if name == "path" => {
+                        *path = resolve_file(path, asset_root)?;
+                    }
+                    ref mut field => resolve_files(field, asset_root)?,
+                }
+            }
+        }
+        Value::Array(ref mut values) => {
+            for value in values {
+                resolve_files(value, asset_root)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn resolve_file(path: &str, asset_root: Option<&Path>) -> Result<String, String> {
+    let root = asset_root.ok_or_else(|| format!("Scenes may not refer to files such as {}", path))?;
+    let relative = Path::new(path);
+    if path.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!("File {} must be a relative path within the asset directory", path));
+    }
+    // Symbolic links could still lead out of the asset directory.
+    let resolved = root.join(relative)
+        .canonicalize()
+        .map_err(|e| format!("Unable to open {}: {}", path, e))?;
+    if !resolved.starts_with(root) {
+        return Err(format!("File {} is outside the asset directory", path));
+    }
+    resolved.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("File {} has a path that is not valid UTF-8", path))
+}
+
+/// Reads the region to render from the query string, checking that it lies within the image.
+fn parse_block(query: &str, scene: &Scene) -> Result<ViewBlock, String> {
+    let mut params = HashMap::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let value = parts.next().unwrap_or("");
+        match name {
+            "x" | "y" | "width" | "height" => {
+                let value: u32 = value.parse()
+                    .map_err(|_| format!("Parameter {} must be a non-negative integer", name))?;
+                params.insert(name, value);
+            }
+            _ => return Err(format!("Unknown parameter {}", name)),
+        }
+    }
+    let x = params.get("x").cloned().unwrap_or(0);
+    let y = params.get("y").cloned().unwrap_or(0);
+    let block = ViewBlock {
+        x,
+        y,
+        width: params.get("width").cloned().unwrap_or_else(|| scene.width.saturating_sub(x)),
+        height: params.get("height").cloned().unwrap_or_else(|| scene.height.saturating_sub(y)),
+    };
+    if block.width == 0 || block.height == 0 ||
+       block.x as u64 + block.width as u64 > scene.width as u64 ||
+       block.y as u64 + block.height as u64 > scene.height as u64 {
+        return Err(format!("Region {}x{} at ({}, {}) is not within the {}x{} image",
+                           block.width,
+                           block.height,
+                           block.x,
+                           block.y,
+                           scene.width,
+                           scene.height));
+    }
+    Ok(block)
+}
+
+/// Waits for a job posted to `/render` and responds with its image.
+fn wait(service: &Service, id: u64) -> HttpResponse {
+    let mut state = service.state.lock().unwrap();
+    loop {
+        if matches!(state.jobs[&id].status, Status::Done { .. } | Status::Failed { .. }) {
+            break;
+        }
+        state = service.finished.wait(state).unwrap();
+    }
+    match state.jobs.remove(&id).unwrap().status {
+        Status::Done { png } => png_response(png),
+        Status::Failed { error } => error_response(500, &error),
+        _ => unreachable!(),
+    }
+}
+
+fn job_status(service: &Service, id: u64) -> HttpResponse {
+    let state = service.state.lock().unwrap();
+    let job = match state.jobs.get(&id) {
+        Some(job) if !job.synchronous => job,
+        _ => return error_response(404, "No such job"),
+    };
+    let body = match job.status {
+        Status::Queued => {
+            let position = state.queue.iter().position(|&(queued, _, _)| queued == id);
+            json!({ "id": id, "status": "queued", "position": position })
+        }
+        Status::Rendering { progress } => {
+            json!({ "id": id, "status": "rendering", "progress": progress })
+        }
+        Status::Done { .. } => json!({ "id": id, "status": "done" }),
+        Status::Failed { ref error } => json!({ "id": id, "status": "failed", "error": error }),
+    };
+    json_response(200, &body)
+}
+
+fn job_image(service: &Service, id: u64) -> HttpResponse {
+    let state = service.state.lock().unwrap();
+    match state.jobs.get(&id) {
+        Some(job) if !job.synchronous => {
+            match job.status {
+                Status::Done { ref png } => png_response(png.clone()),
+                Status::Failed { ref error } => error_response(500, error),
+                _ => error_response(409, "The job has not finished yet"),
+            }
+        }
+        _ => error_response(404, "No such job"),
+    }
+}
+
+fn delete_job(service: &Service, id: u64) -> HttpResponse {
+    let mut state = service.state.lock().unwrap();
+    match state.jobs.get(&id) {
+        Some(&Job { status: Status::Rendering { .. }, .. }) => {
+            return error_response(409, "The job is being rendered");
+        }
+        Some(job) if !job.synchronous => {}
+        _ => return error_response(404, "No such job"),
+    }
+    state.jobs.remove(&id);
+    state.queue.retain(|&(queued, _, _)| queued != id);
+    state.finished.retain(|&finished| finished != id);
+    json_response(200, &json!({ "id": id, "status": "deleted" }))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn png_response(png: Vec<u8>) -> HttpResponse {
+    Response::from_data(png).with_header(header("Content-Type", "image/png"))
+}
+
+fn json_response(status: u16, body: &Value) -> HttpResponse {
+    Response::from_data(serde_json::to_vec(body).unwrap())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn error_response(status: u16, error: &str) -> HttpResponse {
+    json_response(status, &json!({ "error": error }))
+}
+
@@ -0,0 +1,23 @@
+//! Checks how the app handles its command line arguments.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+fn fails_with(args: &[&str], message: &str) {
+    let output = Command::new(env!("CARGO_BIN_EXE_raytracer-app")).args(args).output().unwrap();
+    assert!(!output.status.success(), "{:?} succeeded", args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(message), "{:?}: {}", args, stderr);
+}
+
+#[test]
+fn zero_tile_size() {
+    let scene = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenes/test.json");
+    let image = env::temp_dir().join(format!("{}-zero-tile-size.png", process::id()));
+    let message = "Tile size must be a positive integer";
+    fails_with(&[scene.to_str().unwrap(), image.to_str().unwrap(), "--tile-size", "0"],
+               message);
+    fails_with(&["serve", "--tile-size", "0"], message);
+    assert!(!image.exists());
+}
@@ -0,0 +1,82 @@
+//! Imports the glTF files in `tests/scenes` through the app and compares the renders with the
+//! reference images in `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to overwrite the reference images after an intended change.
+
+extern crate image;
+extern crate serde_json;
+
+use image::GenericImage;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+/// Largest acceptable average difference per color channel, out of 255.
+const MAX_MEAN_DIFFERENCE: f64 = 1.0;
+
+fn test_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(path)
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}-{}", process::id(), name))
+}
+
+fn run(scene: &Path, image: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_raytracer-app"))
+        .arg(scene)
+        .arg(image)
+        .args(["--width", "96"])
+        .output()
+        .unwrap()
+}
+
+fn check_golden(name: &str) {
+    let image_path = temp_path(&format!("{}.png", name));
+    let output = run(&test_path(&format!("scenes/{}.gltf", name)), &image_path);
+    assert!(output.status.success(),
+            "{}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr));
+
+    let golden_path = test_path(&format!("golden/{}.png", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::copy(&image_path, &golden_path).unwrap();
+    }
+    let actual = image::open(&image_path).unwrap();
+    fs::remove_file(&image_path).unwrap();
+    let expected = image::open(&golden_path).unwrap();
+    assert_eq!(actual.dimensions(), expected.dimensions(), "{}: size mismatch", name);
+
+    let (actual, expected) = (actual.to_rgb().into_raw(), expected.to_rgb().into_raw());
+    let total: f64 = actual.iter()
+        .zip(expected.iter())
+        .map(|(&a, &e)| (a as f64 - e as f64).abs())
+        .sum();
+    let mean = total / actual.len() as f64;
+    assert!(mean <= MAX_MEAN_DIFFERENCE,
+            "{}: differs from the golden image by {:.3} on average",
+            name,
+            mean);
+}
+
+#[test]
+fn textured() {
+    check_golden("textured");
+}
+
+#[test]
+fn index_out_of_bounds() {
+    // Shrinking the cube's position accessor leaves its indices pointing past the vertices.
+    let source = fs::read_to_string(test_path("scenes/textured.gltf")).unwrap();
+    let mut document: serde_json::Value = serde_json::from_str(&source).unwrap();
+    document["accessors"][3]["count"] = serde_json::Value::from(4);
+    let scene_path = temp_path("out_of_bounds.gltf");
+    fs::write(&scene_path, serde_json::to_string(&document).unwrap()).unwrap();
+
+    let output = run(&scene_path, &temp_path("out_of_bounds.png"));
+    fs::remove_file(&scene_path).unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("refers to vertex 4 of 4"));
+}
@@ -0,0 +1,199 @@
+//! Runs the render service on an ephemeral port and talks to it over HTTP.
+
+extern crate serde_json;
+
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const EMPTY_SCENE: &str = r#"{"width": 16, "height": 8, "fov": 90.0, "elements": [],
+                              "lights": [], "shadow_bias": 1e-9, "max_recursion_depth": 4}"#;
+
+/// A glass sphere filling a large image, which keeps a worker busy for a while.
+const SLOW_SCENE: &str = r#"{"width": 800, "height": 800, "fov": 90.0, "shadow_bias": 1e-9,
+    "max_recursion_depth": 8,
+    "elements": [{"Sphere": {"center": {"x": 0, "y": 0, "z": -3}, "radius": 2.5,
+        "material": {"coloration": {"Color": {"red": 1, "green": 1, "blue": 1}},
+                     "albedo": 0.18,
+                     "surface": {"Refractive": {"index": 1.5, "transparency": 0.9}}}}}],
+    "lights": [{"Spherical": {"position": {"x": 0, "y": 5, "z": 0},
+                              "color": {"red": 1, "green": 1, "blue": 1},
+                              "intensity": 100}}]}"#;
+
+struct Server {
+    child: Child,
+    address: String,
+}
+impl Server {
+    fn start(args: &[&str]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_raytracer-app"))
+            .args(["serve", "--address", "127.0.0.1:0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let address = line.trim().trim_start_matches("Listening on ").to_string();
+        Server { child, address }
+    }
+
+    /// Sends a request and returns the status code and body of the response.
+    fn request(&self, method: &str, path: &str, body: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(&self.address).unwrap();
+        write!(stream,
+               "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+               method,
+               path,
+               self.address,
+               body.len(),
+               body)
+            .unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..end]).to_string();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, response[end + 4..].to_vec())
+    }
+
+    fn json(&self, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let (status, body) = self.request(method, path, body);
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn submit(&self, scene: &str) -> u64 {
+        let (status, body) = self.json("POST", "/jobs", scene);
+        assert_eq!(status, 202, "{}", body);
+        body["id"].as_u64().unwrap()
+    }
+
+    /// Polls the status of a job until it is `expected`.
+    fn wait_for(&self, id: u64, expected: &str) {
+        let start = Instant::now();
+        loop {
+            let (_, body) = self.json("GET", &format!("/jobs/{}", id), "");
+            if body["status"] == expected {
+                return;
+            }
+            assert!(start.elapsed() < Duration::from_secs(30),
+                    "job {} is still {}",
+                    id,
+                    body);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn job_lifecycle() {
+    let server = Server::start(&[]);
+    let id = server.submit(EMPTY_SCENE);
+    server.wait_for(id, "done");
+
+    let (status, png) = server.request("GET", &format!("/jobs/{}/image", id), "");
+    assert_eq!(status, 200);
+    assert!(png.starts_with(b"\x89PNG"));
+
+    let (status, body) = server.json("DELETE", &format!("/jobs/{}", id), "");
+    assert_eq!((status, &body["status"]), (200, &Value::from("deleted")));
+    assert_eq!(server.json("GET", &format!("/jobs/{}", id), "").0, 404);
+    assert_eq!(server.json("GET", &format!("/jobs/{}/image", id), "").0, 404);
+}
+
+#[test]
+fn synchronous_render() {
+    let server = Server::start(&[]);
+    let (status, png) = server.request("POST", "/render?x=4&width=8", EMPTY_SCENE);
+    assert_eq!(status, 200);
+    assert!(png.starts_with(b"\x89PNG"));
+}
+
+#[test]
+fn full_queue() {
+    let server = Server::start(&["--max-queued", "1"]);
+    let rendering = server.submit(SLOW_SCENE);
+    server.wait_for(rendering, "rendering");
+    let queued = server.submit(EMPTY_SCENE);
+    server.wait_for(queued, "queued");
+
+    let (status, body) = server.json("POST", "/jobs", EMPTY_SCENE);
+    assert_eq!(status, 503, "{}", body);
+    assert_eq!(server.json("POST", "/render", EMPTY_SCENE).0, 503);
+    assert_eq!(server.json("DELETE", &format!("/jobs/{}", rendering), "").0, 409);
+}
+
+#[test]
+fn finished_jobs_are_capped() {
+    let server = Server::start(&["--max-finished", "1"]);
+    let first = server.submit(EMPTY_SCENE);
+    server.wait_for(first, "done");
+    let second = server.submit(EMPTY_SCENE);
+    server.wait_for(second, "done");
+    assert_eq!(server.json("GET", &format!("/jobs/{}", first), "").0, 404);
+}
+
+#[test]
+fn invalid_regions() {
+    let server = Server::start(&[]);
+    for query in &["x=10&width=10", "y=8", "width=0", "x=-1", "x=one", "depth=2"] {
+        let (status, body) = server.json("POST", &format!("/jobs?{}", query), EMPTY_SCENE);
+        assert_eq!(status, 400, "{}: {}", query, body);
+    }
+}
+
+#[test]
+fn oversized_scenes() {
+    let server = Server::start(&["--max-body-size", "1000", "--max-pixels", "10000"]);
+    let padded = format!("{}{}", EMPTY_SCENE, " ".repeat(1000));
+    assert_eq!(server.json("POST", "/jobs", &padded).0, 413);
+    assert_eq!(server.json("POST", "/render", &padded).0, 413);
+    let huge = EMPTY_SCENE.replace(r#""width": 16, "height": 8"#,
+                                   r#""width": 100000, "height": 100000"#);
+    let (status, body) = server.json("POST", "/jobs", &huge);
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(server.json("POST", "/jobs", EMPTY_SCENE).0, 202);
+}
+
+fn textured_scene(path: &str) -> String {
+    format!(r#"{{"width": 16, "height": 8, "fov": 90.0, "lights": [], "shadow_bias": 1e-9,
+                 "max_recursion_depth": 4,
+                 "elements": [{{"Sphere": {{"center": {{"x": 0, "y": 0, "z": -5}}, "radius": 1,
+                     "material": {{"coloration": {{"Texture": {{"path": "{}"}}}},
+                                  "albedo": 0.18, "surface": "Diffuse"}}}}}}]}}"#,
+            path)
+}
+
+#[test]
+fn file_references() {
+    let assets = env::temp_dir().join(format!("assets-{}", process::id()));
+    fs::create_dir_all(&assets).unwrap();
+    let scenes = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenes");
+    fs::copy(scenes.join("checkerboard.png"), assets.join("checkerboard.png")).unwrap();
+
+    let without_assets = Server::start(&[]);
+    let (status, body) = without_assets.json("POST", "/jobs", &textured_scene("checkerboard.png"));
+    assert_eq!(status, 400, "{}", body);
+
+    let with_assets = Server::start(&["--assets", assets.to_str().unwrap()]);
+    let id = with_assets.submit(&textured_scene("checkerboard.png"));
+    with_assets.wait_for(id, "done");
+    let outside = scenes.join("checkerboard.png");
+    for path in &["../checkerboard.png", outside.to_str().unwrap(), "missing.png"] {
+        let (status, body) = with_assets.json("POST", "/jobs", &textured_scene(path));
+        assert_eq!(status, 400, "{}: {}", path, body);
+    }
+    fs::remove_dir_all(&assets).unwrap();
+}
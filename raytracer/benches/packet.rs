@@ -0,0 +1,72 @@
+//! Compares tracing primary rays one at a time with tracing them in packets.
+
+#[macro_use]
+extern crate criterion;
+extern crate raytracer;
+extern crate serde_json;
+
+use criterion::Criterion;
+use raytracer::packet::{PACKET_SIZE, RayPacket};
+use raytracer::rendering::{Ray, trace_pixel, trace_pixels};
+use raytracer::scene::Scene;
+use std::fs::File;
+
+fn load_scene() -> Scene {
+    let file = File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/benches/scenes/spheres.json"))
+        .expect("Failed to open the benchmark scene");
+    serde_json::from_reader(file).expect("Failed to parse the benchmark scene")
+}
+
+/// The pixels of the scene, grouped in 2x2 quads like the renderer does.
+fn quads(scene: &Scene) -> Vec<(u32, u32)> {
+    let mut pixels = vec![];
+    for y in (0..scene.height).step_by(2) {
+        for x in (0..scene.width).step_by(2) {
+            pixels.extend_from_slice(&[(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]);
+        }
+    }
+    pixels.retain(|&(x, y)| x < scene.width && y < scene.height);
+    pixels
+}
+
+fn primary_rays(c: &mut Criterion) {
+    let scene = load_scene();
+    let pixels = quads(&scene);
+    let mut group = c.benchmark_group("primary rays");
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            pixels.iter()
+                .filter_map(|&(x, y)| Ray::create_prime(x, y, &scene))
+                .filter(|ray| scene.trace(ray).unwrap().is_some())
+                .count()
+        })
+    });
+    group.bench_function("packet", |b| {
+        b.iter(|| {
+            pixels.chunks(PACKET_SIZE)
+                .map(|chunk| {
+                    let packet = RayPacket::create_prime(chunk, &scene);
+                    let hits = scene.trace_packet(&packet).unwrap();
+                    hits.iter().filter(|hit| hit.is_some()).count()
+                })
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+fn shaded_pixels(c: &mut Criterion) {
+    let scene = load_scene();
+    let pixels = quads(&scene);
+    let mut group = c.benchmark_group("shaded pixels");
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            pixels.iter().map(|&(x, y)| trace_pixel(&scene, x, y).unwrap()).collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("packet", |b| b.iter(|| trace_pixels(&scene, &pixels).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, primary_rays, shaded_pixels);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+//! Benchmarks of the core tracing functions and of full renders of the reference scenes used by
+//! the golden-image tests.
+
+#[macro_use]
+extern crate criterion;
+extern crate raytracer;
+extern crate serde_json;
+
+use criterion::Criterion;
+use raytracer::ViewBlock;
+use raytracer::rendering::{Ray, cast_ray};
+use raytracer::scene::Scene;
+use std::fs::File;
+
+const REFERENCE_SCENES: &[&str] = &["basic", "spot", "emissive", "fog", "dispersion", "instances"];
+
+/// Texture paths in the reference scenes are relative to the crate root, which cargo runs
+/// benchmarks from.
+fn load_scene(name: &str) -> Scene {
+    let path = format!("{}/tests/scenes/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+    let file = File::open(&path).expect("Failed to open the reference scene");
+    serde_json::from_reader(file).expect("Failed to parse the reference scene")
+}
+
+fn primary_rays(scene: &Scene) -> Vec<Ray> {
+    let mut rays = vec![];
+    for y in 0..scene.height {
+        for x in 0..scene.width {
+            rays.extend(Ray::create_prime(x, y, scene));
+        }
+    }
+    rays
+}
+
+fn trace(c: &mut Criterion) {
+    let scene = load_scene("basic");
+    let rays = primary_rays(&scene);
+    c.bench_function("Scene::trace", |b| {
+        b.iter(|| rays.iter().filter(|ray| scene.trace(ray).unwrap().is_some()).count())
+    });
+}
+
+fn cast(c: &mut Criterion) {
+    let scene = load_scene("basic");
+    let rays = primary_rays(&scene);
+    c.bench_function("cast_ray", |b| {
+        b.iter(|| rays.iter().map(|ray| cast_ray(&scene, ray, 0).unwrap()).collect::<Vec<_>>())
+    });
+}
+
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    for name in REFERENCE_SCENES {
+        let scene = load_scene(name);
+        let block = ViewBlock {
+            x: 0,
+            y: 0,
+            width: scene.width,
+            height: scene.height,
+        };
+        group.bench_function(*name, |b| b.iter(|| raytracer::render(&block, &scene).unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, trace, cast, render);
+criterion_main!(benches);
@@ -0,0 +1,214 @@
+use image::{DynamicImage, GenericImage, Pixel, Rgba};
+use rendering::{BLACK, Ray, face_forward, primary_lighting};
+use scene::{Color, Scene};
+use error::RenderError;
+use sampling::sample_offset;
+use vector::Vector3;
+use ViewBlock;
+
+/// Arbitrary output variables for one primary ray.
+#[derive(Debug, Clone, Copy)]
+pub struct AovSample {
+    /// Distance from the camera to the first hit, or `None` if the ray escaped.
+    pub depth: Option<f64>,
+    pub normal: Vector3,
+    pub albedo: Color,
+    /// Index of the hit element in `Scene::elements`.
+    pub element: Option<usize>,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+/// Per-pixel AOV buffers for a block, stored row by row.
+///
+/// The direct and indirect lighting passes are the parts of the beauty pass described by
+/// `Lighting`, and add up to it.
+pub struct Aovs {
+    pub width: u32,
+    pub height: u32,
+    pub depth: Vec<Option<f64>>,
+    pub normal: Vec<Vector3>,
+    pub albedo: Vec<Color>,
+    pub element: Vec<Option<usize>>,
+    pub direct: Vec<Color>,
+    pub indirect: Vec<Color>,
+}
+
+impl AovSample {
+    /// The sample of a ray that hit nothing, in which only the direct pass can be lit.
+    fn miss(direct: Color) -> AovSample {
+        AovSample {
+            depth: None,
+            normal: Vector3::zero(),
+            albedo: BLACK,
+            element: None,
+            direct,
+            indirect: BLACK,
+        }
+    }
+}
+
+/// The AOVs of a primary ray, whose lighting is shaded the same way as the beauty pass.
+pub fn sample_aovs(scene: &Scene, ray: &Ray) -> Result<AovSample, RenderError> {
+    let intersection = scene.trace(ray)?;
+    let lighting = primary_lighting(scene, ray, intersection.as_ref())?;
+    let sample = match intersection {
+        None => AovSample::miss(lighting.direct),
+        Some(intersection) => {
+            let hit = ray.origin + (ray.direction * intersection.distance);
+            let material = intersection.material;
+            AovSample {
+                depth: Some(intersection.distance),
+                normal: face_forward(intersection.surface_normal(&hit), ray.direction),
+                albedo: material.coloration.color(&intersection.texture_coords(&hit)),
+                element: Some(intersection.index),
+                direct: lighting.direct,
+                indirect: lighting.indirect,
+            }
+        }
+    };
+    Ok(sample)
+}
+
+/// Renders the AOVs for a block, through the center of each pixel.
+pub fn render_aovs(block: &ViewBlock, scene: &Scene) -> Result<Aovs, RenderError> {
+    let samples = vec![1; (block.width * block.height) as usize];
+    render_aovs_with_samples(block, scene, &samples)
+}
+
+/// Renders the AOVs for a block, averaging as many samples per pixel as the beauty pass took, as
+/// given row by row by `Checkpoint::samples`. The samples are taken at the same points of the
+/// pixels as adaptive sampling does, so that the AOVs match the beauty pass. The element ID is
+/// the one seen through the first sample, and the depth is averaged over the samples that hit.
+pub fn render_aovs_with_samples(block: &ViewBlock,
+                                scene: &Scene,
+                                samples: &[u32])
+                                -> Result<Aovs, RenderError> {
+    scene.validate()?;
+    let pixels = (block.width * block.height) as usize;
+    if samples.len() != pixels {
+        return Err(RenderError::SizeMismatch {
+            expected: pixels,
+            actual: samples.len(),
+        });
+    }
+    let mut aovs = Aovs {
+        width: block.width,
+        height: block.height,
+        depth: Vec::with_capacity(pixels),
+        normal: Vec::with_capacity(pixels),
+        albedo: Vec::with_capacity(pixels),
+        element: Vec::with_capacity(pixels),
+        direct: Vec::with_capacity(pixels),
+        indirect: Vec::with_capacity(pixels),
+    };
+    for y in 0..block.height {
+        for x in 0..block.width {
+            let count = samples[(y * block.width + x) as usize].max(1);
+            let mut total = AovSample::miss(BLACK);
+            let (mut depth, mut hits) = (0.0, 0);
+            for index in 0..count {
+                let (px, py) = (x + block.x, y + block.y);
+                let sample = match Ray::create_prime_sample(px, py, sample_offset(index), scene) {
+                    Some(ray) => sample_aovs(scene, &ray)?,
+                    None => AovSample::miss(BLACK),
+                };
+                if let Some(distance) = sample.depth {
+                    depth += distance;
+                    hits += 1;
+                }
+                if index == 0 {
+                    total.element = sample.element;
+                }
+                total.normal = total.normal + sample.normal;
+                total.albedo = total.albedo + sample.albedo;
+                total.direct = total.direct + sample.direct;
+                total.indirect = total.indirect + sample.indirect;
+            }
+            let weight = 1.0 / count as f32;
+            aovs.depth.push(if hits > 0 { Some(depth / hits as f64) } else { None });
+            aovs.normal.push(if total.normal.length() > 0.0 {
+                total.normal.normalize()
+            } else {
+                total.normal
+            });
+            aovs.albedo.push(total.albedo * weight);
+            aovs.element.push(total.element);
+            aovs.direct.push(total.direct * weight);
+            aovs.indirect.push(total.indirect * weight);
+        }
+    }
+    Ok(aovs)
+}
+
+impl Aovs {
+    fn to_image<F>(&self, pixel: F) -> DynamicImage
+        where F: Fn(usize) -> Rgba<u8>
+    {
+        let mut image = DynamicImage::new_rgb8(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.put_pixel(x, y, pixel((y * self.width + x) as usize));
+            }
+        }
+        image
+    }
+
+    /// Hit distance scaled so that the farthest hit is white. Misses are white as well.
+    pub fn depth_image(&self) -> DynamicImage {
+        let max_depth = self.depth.iter().filter_map(|d| *d).fold(0.0, f64::max);
+        self.to_image(|i| {
+            let value = match self.depth[i] {
+                Some(d) if max_depth > 0.0 => (d / max_depth * 255.0) as u8,
+                Some(_) => 0,
+                None => 255,
+            };
+            Rgba::from_channels(value, value, value, 255)
+        })
+    }
+
+    /// World-space normals, mapped from [-1, 1] to [0, 255] per axis.
+    pub fn normal_image(&self) -> DynamicImage {
+        self.to_image(|i| {
+            let n = self.normal[i];
+            let encode = |v: f64| ((v * 0.5 + 0.5) * 255.0) as u8;
+            Rgba::from_channels(encode(n.x), encode(n.y), encode(n.z), 255)
+        })
+    }
+
+    pub fn albedo_image(&self) -> DynamicImage {
+        self.to_image(|i| self.albedo[i].clamp().to_rgba())
+    }
+
+    /// A distinct flat color per element, with black for the background.
+    pub fn element_image(&self) -> DynamicImage {
+        self.to_image(|i| match self.element[i] {
+            Some(index) => id_color(index),
+            None => Rgba::from_channels(0, 0, 0, 255),
+        })
+    }
+
+    pub fn direct_image(&self) -> DynamicImage {
+        self.to_image(|i| self.direct[i].to_rgba())
+    }
+
+    pub fn indirect_image(&self) -> DynamicImage {
+        self.to_image(|i| self.indirect[i].to_rgba())
+    }
+}
+
+fn id_color(index: usize) -> Rgba<u8> {
+    // Spread consecutive indices around the hue circle using the golden ratio.
+    let hue = (index as f32 * 0.618_034 + 0.1) % 1.0;
+    let sector = hue * 6.0;
+    let f = sector.fract();
+    let (r, g, b) = match sector as u32 {
+        0 => (1.0, f, 0.0),
+        1 => (1.0 - f, 1.0, 0.0),
+        2 => (0.0, 1.0, f),
+        3 => (0.0, 1.0 - f, 1.0),
+        4 => (f, 0.0, 1.0),
+        _ => (1.0, 0.0, 1.0 - f),
+    };
+    Rgba::from_channels((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255)
+}
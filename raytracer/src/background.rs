@@ -0,0 +1,338 @@
+use rendering::TextureCoords;
+use scene::{Color, DirectionalLight, Light, Texture, load_texture};
+use vector::Vector3;
+use serde::{Deserialize, Deserializer};
+use std::f64::consts::PI;
+
+/// What a ray sees when it misses every element of the scene.
+///
+/// Environment maps and the daylight sky also light the scene. Their lighting is approximated by
+/// a grid of directional lights, one per cell of the sky, which is computed once when the
+/// background is created. Cells that are black, or below the horizon unless an environment map
+/// asks for them, get no light.
+#[derive(Deserialize, Serialize, Debug)]
+pub enum Background {
+    Color(Color),
+    /// Blends from the horizon color to the zenith color with the elevation of the ray. Rays
+    /// pointing below the horizon get the horizon color.
+    Gradient { horizon: Color, zenith: Color },
+    Environment(#[serde(deserialize_with = "load_environment")] EnvironmentMap),
+    Daylight(#[serde(deserialize_with = "load_daylight")] Daylight),
+}
+impl Default for Background {
+    fn default() -> Background {
+        Background::Color(Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        })
+    }
+}
+impl Background {
+    pub fn color(&self, direction: &Vector3) -> Color {
+        match *self {
+            Background::Color(c) => c,
+            Background::Gradient { horizon, zenith } => {
+                let t = direction.y.max(0.0) as f32;
+                horizon * (1.0 - t) + zenith * t
+            }
+            Background::Environment(ref e) => e.color(direction),
+            Background::Daylight(ref d) => d.color(direction),
+        }
+    }
+
+    /// The lights standing in for the light emitted by the background.
+    pub fn lights(&self) -> &[Light] {
+        match *self {
+            Background::Color(_) |
+            Background::Gradient { .. } => &[],
+            Background::Environment(ref e) => &e.lights,
+            Background::Daylight(ref d) => &d.lights,
+        }
+    }
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_light_resolution() -> u32 {
+    8
+}
+
+/// An equirectangular (latitude-longitude) image surrounding the scene. The center of the image
+/// is straight ahead of the camera, along -z.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct EnvironmentMap {
+    #[serde(deserialize_with = "load_texture")]
+    pub texture: Texture,
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    /// Number of light cells around the horizon; half as many are used from pole to pole.
+    #[serde(default = "default_light_resolution")]
+    pub light_resolution: u32,
+    /// Whether the half of the map below the horizon lights the scene as well. It is usually
+    /// hidden by the ground of the scene, so by default it is only seen.
+    #[serde(default)]
+    pub light_from_below: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    lights: Vec<Light>,
+}
+impl EnvironmentMap {
+    pub fn new(texture: Texture,
+               intensity: f32,
+               light_resolution: u32,
+               light_from_below: bool)
+               -> EnvironmentMap {
+        let mut map = EnvironmentMap {
+            texture,
+            intensity,
+            light_resolution,
+            light_from_below,
+            lights: vec![],
+        };
+        map.lights = sky_lights(light_resolution, light_from_below, |d| map.color(d));
+        map
+    }
+
+    pub fn color(&self, direction: &Vector3) -> Color {
+        let coords = TextureCoords {
+            x: (0.5 + direction.x.atan2(-direction.z) / (2.0 * PI)) as f32,
+            y: (direction.y.clamp(-1.0, 1.0).acos() / PI) as f32,
+        };
+        self.texture.color(&coords) * self.intensity
+    }
+}
+
+fn load_environment<D>(deserializer: D) -> Result<EnvironmentMap, D::Error>
+    where D: Deserializer
+{
+    let map = EnvironmentMap::deserialize(deserializer)?;
+    Ok(EnvironmentMap::new(map.texture, map.intensity, map.light_resolution, map.light_from_below))
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+/// The Preetham analytic daylight model. Sky radiance is normalized so that the zenith has a
+/// luminance of `intensity`; the sun itself is added as a directional light.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Daylight {
+    /// Direction pointing towards the sun.
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub sun_direction: Vector3,
+    /// Haziness of the atmosphere, from 2 (very clear) to 10 (hazy).
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    #[serde(default = "default_intensity")]
+    pub sun_intensity: f32,
+    #[serde(default = "default_light_resolution")]
+    pub light_resolution: u32,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    lights: Vec<Light>,
+}
+
+/// Angular radius of the sun as seen from the earth.
+const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+
+impl Daylight {
+    pub fn new(sun_direction: Vector3,
+               turbidity: f64,
+               intensity: f32,
+               sun_intensity: f32,
+               light_resolution: u32)
+               -> Daylight {
+        let mut sky = Daylight {
+            sun_direction: sun_direction.normalize(),
+            turbidity,
+            intensity,
+            sun_intensity,
+            light_resolution,
+            lights: vec![],
+        };
+        // The model has no ground; the sky below the horizon is only there to be seen.
+        let mut lights = sky_lights(light_resolution, false, |d| sky.sky_color(d));
+        if sky.sun_direction.y > 0.0 {
+            lights.push(Light::Directional(DirectionalLight {
+                direction: -sky.sun_direction,
+                color: Color {
+                    red: 1.0,
+                    green: 1.0,
+                    blue: 1.0,
+                },
+                intensity: sun_intensity,
+            }));
+        }
+        sky.lights = lights;
+        sky
+    }
+
+    pub fn color(&self, direction: &Vector3) -> Color {
+        let cos_sun = direction.dot(&self.sun_direction);
+        if self.sun_direction.y > 0.0 && cos_sun > SUN_ANGULAR_RADIUS.cos() {
+            // Spread the sun's power over its disc so that looking at it is consistent with the
+            // light it casts.
+            let solid_angle = 2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos());
+            let radiance = self.sun_intensity / solid_angle as f32;
+            return Color {
+                red: radiance,
+                green: radiance,
+                blue: radiance,
+            };
+        }
+        self.sky_color(direction)
+    }
+
+    fn sky_color(&self, direction: &Vector3) -> Color {
+        let t = self.turbidity;
+        let theta_s = self.sun_direction.y.clamp(-1.0, 1.0).acos();
+        // Below the horizon, use the sky just above it.
+        let cos_theta = direction.y.max(0.01);
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let perez = |a: f64, b: f64, c: f64, d: f64, e: f64, theta_cos: f64, gamma: f64| {
+            (1.0 + a * (b / theta_cos).exp()) *
+            (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+        };
+        let distribution = |coefficients: [f64; 5]| {
+            let [a, b, c, d, e] = coefficients;
+            perez(a, b, c, d, e, cos_theta, gamma) / perez(a, b, c, d, e, 1.0, theta_s)
+        };
+
+        let lum = distribution([0.1787 * t - 1.4630,
+                                -0.3554 * t + 0.4275,
+                                -0.0227 * t + 5.3251,
+                                0.1206 * t - 2.5771,
+                                -0.0670 * t + 0.3703]);
+        let x = zenith_chromaticity(t,
+                                    theta_s,
+                                    [0.00166, -0.00375, 0.00209, 0.0],
+                                    [-0.02903, 0.06377, -0.03202, 0.00394],
+                                    [0.11693, -0.21196, 0.06052, 0.25886]) *
+                distribution([-0.0193 * t - 0.2592,
+                              -0.0665 * t + 0.0008,
+                              -0.0004 * t + 0.2125,
+                              -0.0641 * t - 0.8989,
+                              -0.0033 * t + 0.0452]);
+        let y = zenith_chromaticity(t,
+                                    theta_s,
+                                    [0.00275, -0.00610, 0.00317, 0.0],
+                                    [-0.04214, 0.08970, -0.04153, 0.00516],
+                                    [0.15346, -0.26756, 0.06670, 0.26688]) *
+                distribution([-0.0167 * t - 0.2608,
+                              -0.0950 * t + 0.0092,
+                              -0.0079 * t + 0.2102,
+                              -0.0441 * t - 1.6537,
+                              -0.0109 * t + 0.0529]);
+
+        xyy_to_rgb(x, y, lum) * self.intensity
+    }
+}
+
+fn zenith_chromaticity(t: f64, theta_s: f64, t2: [f64; 4], t1: [f64; 4], t0: [f64; 4]) -> f64 {
+    let thetas = [theta_s * theta_s * theta_s, theta_s * theta_s, theta_s, 1.0];
+    let dot = |c: [f64; 4]| c.iter().zip(thetas.iter()).map(|(a, b)| a * b).sum::<f64>();
+    t * t * dot(t2) + t * dot(t1) + dot(t0)
+}
+
+/// Converts a CIE xyY color to linear sRGB.
+pub fn xyy_to_rgb(x: f64, y: f64, lum: f64) -> Color {
+    if y <= 0.0 {
+        return Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        };
+    }
+    let big_x = x / y * lum;
+    let big_z = (1.0 - x - y) / y * lum;
+    xyz_to_rgb(big_x, lum, big_z)
+}
+
+/// Converts a CIE XYZ color to linear sRGB, clipping negative (out of gamut) components.
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    Color {
+        red: (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0) as f32,
+        green: (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0) as f32,
+        blue: (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0) as f32,
+    }
+}
+
+fn load_daylight<D>(deserializer: D) -> Result<Daylight, D::Error>
+    where D: Deserializer
+{
+    let sky = Daylight::deserialize(deserializer)?;
+    Ok(Daylight::new(sky.sun_direction,
+                     sky.turbidity,
+                     sky.intensity,
+                     sky.sun_intensity,
+                     sky.light_resolution))
+}
+
+const CELL_SAMPLES: u32 = 8;
+
+/// Approximates the light arriving from every direction with one directional light per cell of
+/// a latitude-longitude grid. Each light carries the average radiance of its cell times the solid
+/// angle the cell covers. Cells that give no light, and those entirely below the horizon unless
+/// `below_horizon` is set, are left out.
+fn sky_lights<F>(resolution: u32, below_horizon: bool, radiance: F) -> Vec<Light>
+    where F: Fn(&Vector3) -> Color
+{
+    let columns = resolution.max(1);
+    let rows = (columns / 2).max(1);
+    let mut lights = vec![];
+    for row in 0..rows {
+        let theta0 = PI * row as f64 / rows as f64;
+        let theta1 = PI * (row + 1) as f64 / rows as f64;
+        if !below_horizon && theta0 >= PI / 2.0 {
+            break;
+        }
+        let solid_angle = 2.0 * PI / columns as f64 * (theta0.cos() - theta1.cos());
+        for column in 0..columns {
+            let mut sum = Color {
+                red: 0.0,
+                green: 0.0,
+                blue: 0.0,
+            };
+            for sy in 0..CELL_SAMPLES {
+                for sx in 0..CELL_SAMPLES {
+                    let u = (column as f64 + (sx as f64 + 0.5) / CELL_SAMPLES as f64) /
+                            columns as f64;
+                    let v = (row as f64 + (sy as f64 + 0.5) / CELL_SAMPLES as f64) /
+                            rows as f64;
+                    sum = sum + radiance(&equirect_direction(u, v));
+                }
+            }
+            let average = sum * (1.0 / (CELL_SAMPLES * CELL_SAMPLES) as f32);
+            let power = average.red.max(average.green).max(average.blue);
+            if power <= 0.0 {
+                continue;
+            }
+
+            let u = (column as f64 + 0.5) / columns as f64;
+            let v = (row as f64 + 0.5) / rows as f64;
+            lights.push(Light::Directional(DirectionalLight {
+                direction: -equirect_direction(u, v),
+                color: average * (1.0 / power),
+                intensity: power * solid_angle as f32,
+            }));
+        }
+    }
+    lights
+}
+
+/// The direction through a point of an equirectangular image, given in [0, 1] coordinates.
+fn equirect_direction(u: f64, v: f64) -> Vector3 {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    Vector3 {
+        x: theta.sin() * phi.sin(),
+        y: theta.cos(),
+        z: -theta.sin() * phi.cos(),
+    }
+}
@@ -0,0 +1,190 @@
+use background::Background;
+use camera::{Projection, Stereo};
+use error::RenderError;
+use instance::{Group, Instance, Transform, TransformStep};
+use media::{Fog, Volume, default_volume_steps};
+use photon::CausticSettings;
+use light_sampling::LightSampling;
+use sampling::AdaptiveSampling;
+use point::Point;
+use scene::{Element, Light, Material, Plane, Scene, SceneCache, Sphere, Triangle};
+use spectral::SpectralSettings;
+use vector::Vector3;
+use std::collections::HashMap;
+
+/// Builds a `Scene` in code, starting from a scene with a 90 degree perspective camera, a black
+/// background and no elements or lights.
+///
+/// The camera sits at the origin looking down -z, with +y up. The built scene serializes to the
+/// same JSON that scene files are written in.
+pub struct SceneBuilder {
+    scene: Scene,
+}
+impl SceneBuilder {
+    pub fn new(width: u32, height: u32) -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene {
+                width,
+                height,
+                fov: 90.0,
+                projection: Projection::Perspective,
+                stereo: None,
+                elements: vec![],
+                lights: vec![],
+                background: Background::default(),
+                fog: None,
+                volumes: vec![],
+                volume_steps: default_volume_steps(),
+                spectral: None,
+                caustics: None,
+                adaptive_sampling: None,
+                light_sampling: None,
+                groups: HashMap::new(),
+                shadow_bias: 1e-9,
+                max_recursion_depth: 10,
+                cache: SceneCache::default(),
+            },
+        }
+    }
+
+    /// Uses a perspective camera with the given vertical field of view, in degrees.
+    pub fn perspective(mut self, fov: f64) -> SceneBuilder {
+        self.scene.projection = Projection::Perspective;
+        self.scene.fov = fov;
+        self
+    }
+
+    /// Uses an orthographic camera covering `height` units of the scene vertically.
+    pub fn orthographic(mut self, height: f64) -> SceneBuilder {
+        self.scene.projection = Projection::Orthographic { height };
+        self
+    }
+
+    /// Uses a fisheye camera with the given field of view, in degrees, across the height of the
+    /// image.
+    pub fn fisheye(mut self, fov: f64) -> SceneBuilder {
+        self.scene.projection = Projection::Fisheye;
+        self.scene.fov = fov;
+        self
+    }
+
+    /// Renders a full panorama. The image should be twice as wide as it is high.
+    pub fn equirectangular(mut self) -> SceneBuilder {
+        self.scene.projection = Projection::Equirectangular;
+        self
+    }
+
+    pub fn stereo(mut self, eye_separation: f64) -> SceneBuilder {
+        self.scene.stereo = Some(Stereo { eye_separation });
+        self
+    }
+
+    pub fn element(mut self, element: Element) -> SceneBuilder {
+        self.scene.elements.push(element);
+        self
+    }
+
+    pub fn sphere(self, center: Point, radius: f64, material: Material) -> SceneBuilder {
+        self.element(Element::Sphere(Sphere {
+            center,
+            radius,
+            material,
+        }))
+    }
+
+    /// A plane seen from the side `up` points to, such as `(0, 1, 0)` for a floor. Scene files
+    /// give the opposite normal, so the plane is stored with `up` reversed.
+    pub fn plane(self, origin: Point, up: Vector3, material: Material) -> SceneBuilder {
+        self.element(Element::Plane(Plane {
+            origin,
+            normal: -up.normalize(),
+            material,
+        }))
+    }
+
+    /// A flat-shaded triangle, whose front face is the one from which the vertices appear
+    /// counter-clockwise.
+    pub fn triangle(self, vertices: [Point; 3], material: Material) -> SceneBuilder {
+        self.element(Element::Triangle(Triangle {
+            vertices,
+            normals: None,
+            tex_coords: None,
+            material,
+        }))
+    }
+
+    /// Defines a named group of elements that can be placed with `instance`.
+    pub fn group(mut self, name: &str, elements: Vec<Element>) -> SceneBuilder {
+        self.scene.groups.insert(name.to_string(), Group::new(elements));
+        self
+    }
+
+    /// Places a copy of a group, transformed by the steps in order.
+    pub fn instance(self, group: &str, steps: Vec<TransformStep>) -> SceneBuilder {
+        self.element(Element::Instance(Instance {
+            group: group.to_string(),
+            transform: Transform::new(steps),
+        }))
+    }
+
+    pub fn light(mut self, light: Light) -> SceneBuilder {
+        self.scene.lights.push(light);
+        self
+    }
+
+    pub fn background(mut self, background: Background) -> SceneBuilder {
+        self.scene.background = background;
+        self
+    }
+
+    pub fn fog(mut self, fog: Fog) -> SceneBuilder {
+        self.scene.fog = Some(fog);
+        self
+    }
+
+    pub fn volume(mut self, volume: Volume) -> SceneBuilder {
+        self.scene.volumes.push(volume);
+        self
+    }
+
+    pub fn volume_steps(mut self, steps: u32) -> SceneBuilder {
+        self.scene.volume_steps = steps;
+        self
+    }
+
+    pub fn spectral(mut self, settings: SpectralSettings) -> SceneBuilder {
+        self.scene.spectral = Some(settings);
+        self
+    }
+
+    pub fn caustics(mut self, settings: CausticSettings) -> SceneBuilder {
+        self.scene.caustics = Some(settings);
+        self
+    }
+
+    pub fn adaptive_sampling(mut self, settings: AdaptiveSampling) -> SceneBuilder {
+        self.scene.adaptive_sampling = Some(settings);
+        self
+    }
+
+    pub fn light_sampling(mut self, settings: LightSampling) -> SceneBuilder {
+        self.scene.light_sampling = Some(settings);
+        self
+    }
+
+    pub fn shadow_bias(mut self, bias: f64) -> SceneBuilder {
+        self.scene.shadow_bias = bias;
+        self
+    }
+
+    pub fn max_recursion_depth(mut self, depth: u32) -> SceneBuilder {
+        self.scene.max_recursion_depth = depth;
+        self
+    }
+
+    /// Finishes the scene, checking that it can be rendered.
+    pub fn build(self) -> Result<Scene, RenderError> {
+        self.scene.validate()?;
+        Ok(self.scene)
+    }
+}
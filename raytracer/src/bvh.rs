@@ -0,0 +1,344 @@
+use point::Point;
+use packet::{Lanes, PACKET_SIZE, PacketIntersectable, RayPacket};
+use rendering::{Intersectable, Ray};
+use scene::{Element, Plane, Sphere, Triangle};
+use std::f64;
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+impl BoundingBox {
+    pub fn empty() -> BoundingBox {
+        BoundingBox {
+            min: Point::from_one(f64::INFINITY),
+            max: Point::from_one(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Point {
+                x: self.min.x.min(other.min.x),
+                y: self.min.y.min(other.min.y),
+                z: self.min.z.min(other.min.z),
+            },
+            max: Point {
+                x: self.max.x.max(other.max.x),
+                y: self.max.y.max(other.max.y),
+                z: self.max.z.max(other.max.z),
+            },
+        }
+    }
+
+    pub fn include(&self, point: &Point) -> BoundingBox {
+        self.union(&BoundingBox {
+            min: *point,
+            max: *point,
+        })
+    }
+
+    pub fn centroid(&self) -> Point {
+        Point {
+            x: (self.min.x + self.max.x) * 0.5,
+            y: (self.min.y + self.max.y) * 0.5,
+            z: (self.min.z + self.max.z) * 0.5,
+        }
+    }
+
+    pub fn corners(&self) -> [Point; 8] {
+        let (a, b) = (self.min, self.max);
+        [Point { x: a.x, y: a.y, z: a.z },
+         Point { x: b.x, y: a.y, z: a.z },
+         Point { x: a.x, y: b.y, z: a.z },
+         Point { x: b.x, y: b.y, z: a.z },
+         Point { x: a.x, y: a.y, z: b.z },
+         Point { x: b.x, y: a.y, z: b.z },
+         Point { x: a.x, y: b.y, z: b.z },
+         Point { x: b.x, y: b.y, z: b.z }]
+    }
+
+    /// The distance at which the ray enters the box, if it hits it before `max_distance`.
+    pub fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<f64> {
+        self.range(ray, max_distance).map(|(t_near, _)| t_near)
+    }
+
+    /// The distances at which the ray enters and leaves the box, clipped to `[0, max_distance]`.
+    pub fn range(&self, ray: &Ray, max_distance: f64) -> Option<(f64, f64)> {
+        let mut t_near: f64 = 0.0;
+        let mut t_far = max_distance;
+        let axes = [(ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+                    (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+                    (ray.origin.z, ray.direction.z, self.min.z, self.max.z)];
+        for &(origin, direction, min, max) in &axes {
+            let inv = 1.0 / direction;
+            let t0 = (min - origin) * inv;
+            let t1 = (max - origin) * inv;
+            // f64::min/max ignore the NaNs produced by rays lying in a slab's boundary plane.
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+            if t_near > t_far {
+                return None;
+            }
+        }
+        Some((t_near, t_far))
+    }
+
+    /// Whether any active ray of the packet hits the box before its own maximum distance.
+    pub fn intersect_packet(&self, packet: &RayPacket, max_distance: &Lanes) -> bool {
+        let mut t_near: Lanes = [0.0; PACKET_SIZE];
+        let mut t_far = *max_distance;
+        let axes = [(&packet.origin.x, &packet.direction.x, self.min.x, self.max.x),
+                    (&packet.origin.y, &packet.direction.y, self.min.y, self.max.y),
+                    (&packet.origin.z, &packet.direction.z, self.min.z, self.max.z)];
+        for &(origin, direction, min, max) in &axes {
+            for i in 0..PACKET_SIZE {
+                let inv = 1.0 / direction[i];
+                let t0 = (min - origin[i]) * inv;
+                let t1 = (max - origin[i]) * inv;
+                t_near[i] = t_near[i].max(t0.min(t1));
+                t_far[i] = t_far[i].min(t0.max(t1));
+            }
+        }
+        (0..PACKET_SIZE).any(|i| packet.active[i] && t_near[i] <= t_far[i])
+    }
+}
+
+pub trait Bounded {
+    /// The bounds of the shape, or `None` if it is infinite.
+    fn bounding_box(&self) -> Option<BoundingBox>;
+}
+impl Bounded for Sphere {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let r = self.radius;
+        Some(BoundingBox {
+            min: Point {
+                x: self.center.x - r,
+                y: self.center.y - r,
+                z: self.center.z - r,
+            },
+            max: Point {
+                x: self.center.x + r,
+                y: self.center.y + r,
+                z: self.center.z + r,
+            },
+        })
+    }
+}
+impl Bounded for Plane {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
+}
+impl Bounded for Triangle {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(self.vertices.iter().fold(BoundingBox::empty(), |b, v| b.include(v)))
+    }
+}
+impl Bounded for Element {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        match *self {
+            Element::Sphere(ref s) => s.bounding_box(),
+            Element::Plane(ref p) => p.bounding_box(),
+            Element::Triangle(ref t) => t.bounding_box(),
+            Element::Sdf(ref s) => s.shape.bounding_box(),
+            Element::Instance(_) => None,
+        }
+    }
+}
+
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        bounds: BoundingBox,
+        elements: Vec<usize>,
+    },
+    Branch {
+        bounds: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+impl Node {
+    fn bounds(&self) -> &BoundingBox {
+        match *self {
+            Node::Leaf { ref bounds, .. } |
+            Node::Branch { ref bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A bounding volume hierarchy over a list of elements, referring to them by index. Unbounded
+/// elements such as planes are kept aside and tested against every ray.
+#[derive(Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    unbounded: Vec<usize>,
+}
+impl Bvh {
+    pub fn build(elements: &[Element]) -> Bvh {
+        let mut bvh = Bvh::default();
+        let mut bounded = vec![];
+        for (i, element) in elements.iter().enumerate() {
+            match element.bounding_box() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => bvh.unbounded.push(i),
+            }
+        }
+        if !bounded.is_empty() {
+            bvh.build_node(&mut bounded);
+        }
+        bvh
+    }
+
+    /// The bounds of all the bounded elements.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        self.nodes.first().map(|n| *n.bounds())
+    }
+
+    pub fn has_unbounded(&self) -> bool {
+        !self.unbounded.is_empty()
+    }
+
+    fn build_node(&mut self, items: &mut [(usize, BoundingBox)]) -> usize {
+        let bounds = items.iter().fold(BoundingBox::empty(), |b, item| b.union(&item.1));
+        let index = self.nodes.len();
+        if items.len() <= MAX_LEAF_SIZE {
+            self.nodes.push(Node::Leaf {
+                bounds,
+                elements: items.iter().map(|item| item.0).collect(),
+            });
+            return index;
+        }
+
+        // Split at the median centroid along the axis where the centroids are most spread out.
+        let centroids = items.iter()
+            .fold(BoundingBox::empty(), |b, item| b.include(&item.1.centroid()));
+        let extent = centroids.max - centroids.min;
+        let axis = |p: &Point| if extent.x >= extent.y && extent.x >= extent.z {
+            p.x
+        } else if extent.y >= extent.z {
+            p.y
+        } else {
+            p.z
+        };
+        items.sort_by(|a, b| axis(&a.1.centroid()).total_cmp(&axis(&b.1.centroid())));
+
+        // Reserve this node's slot before building the children.
+        self.nodes.push(Node::Leaf {
+            bounds,
+            elements: vec![],
+        });
+        let middle = items.len() / 2;
+        let (left_items, right_items) = items.split_at_mut(middle);
+        let left = self.build_node(left_items);
+        let right = self.build_node(right_items);
+        self.nodes[index] = Node::Branch {
+            bounds,
+            left,
+            right,
+        };
+        index
+    }
+
+    /// Finds the closest element hit by the ray, returning its distance and index. The number of
+    /// elements tested is added to `tests`.
+    pub fn trace(&self,
+                 elements: &[Element],
+                 ray: &Ray,
+                 tests: &mut u64)
+                 -> Option<(f64, usize)> {
+        let mut closest: Option<(f64, usize)> = None;
+
+        for &index in &self.unbounded {
+            *tests += 1;
+            if let Some(distance) = elements[index].intersect(ray) {
+                consider(distance, index, &mut closest);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let max_distance = closest.map_or(f64::INFINITY, |c| c.0);
+            if self.nodes[node].bounds().intersect(ray, max_distance).is_none() {
+                continue;
+            }
+            match self.nodes[node] {
+                Node::Leaf { elements: ref indices, .. } => {
+                    *tests += indices.len() as u64;
+                    for &index in indices {
+                        if let Some(distance) = elements[index].intersect(ray) {
+                            consider(distance, index, &mut closest);
+                        }
+                    }
+                }
+                Node::Branch { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        closest
+    }
+
+    /// Like `trace`, for every ray of the packet at once. A node is visited as soon as one of the
+    /// rays may hit something inside it.
+    pub fn trace_packet(&self,
+                        elements: &[Element],
+                        packet: &RayPacket,
+                        tests: &mut u64)
+                        -> [Option<(f64, usize)>; PACKET_SIZE] {
+        let mut closest = [None; PACKET_SIZE];
+        let lanes = packet.active.iter().filter(|&&a| a).count() as u64;
+        let mut test = |index: usize, closest: &mut [Option<(f64, usize)>; PACKET_SIZE]| {
+            *tests += lanes;
+            let distances = elements[index].intersect_packet(packet);
+            for (distance, closest) in distances.iter().zip(closest.iter_mut()) {
+                if *distance != f64::INFINITY {
+                    consider(*distance, index, closest);
+                }
+            }
+        };
+
+        for &index in &self.unbounded {
+            test(index, &mut closest);
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let max_distance: Lanes =
+                ::std::array::from_fn(|i| closest[i].map_or(f64::INFINITY, |c| c.0));
+            if !self.nodes[node].bounds().intersect_packet(packet, &max_distance) {
+                continue;
+            }
+            match self.nodes[node] {
+                Node::Leaf { elements: ref indices, .. } => {
+                    for &index in indices {
+                        test(index, &mut closest);
+                    }
+                }
+                Node::Branch { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        closest
+    }
+}
+
+fn consider(distance: f64, index: usize, closest: &mut Option<(f64, usize)>) {
+    if closest.is_none_or(|(d, _)| distance < d) {
+        *closest = Some((distance, index));
+    }
+}
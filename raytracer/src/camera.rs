@@ -0,0 +1,132 @@
+use point::Point;
+use vector::Vector3;
+use std::f64::consts::PI;
+
+/// How the camera maps the image to rays. The camera sits at the origin, looking down -z with +y
+/// up, and the scene's `fov` is measured across the height of the image.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    /// A pinhole camera. The field of view must be less than 180 degrees.
+    #[default]
+    Perspective,
+    /// Parallel rays along -z, covering `height` units of the scene vertically. The field of view
+    /// is not used.
+    Orthographic { height: f64 },
+    /// An equidistant fisheye: the angle between a ray and the view axis grows in proportion to
+    /// the distance of its pixel from the center of the image. The field of view spans the height
+    /// of the image and may reach 360 degrees; pixels outside the circle it covers are left black.
+    Fisheye,
+    /// A full panorama, with longitude along the width of the image and latitude along its
+    /// height, and straight ahead in the center. The field of view is not used.
+    Equirectangular,
+}
+impl Projection {
+    /// Whether the projection can use the field of view, in degrees. Projections that ignore it
+    /// accept any.
+    pub fn supports_fov(&self, fov: f64) -> bool {
+        match *self {
+            Projection::Perspective => fov > 0.0 && fov < 180.0,
+            Projection::Fisheye => fov > 0.0 && fov <= 360.0,
+            Projection::Orthographic { .. } |
+            Projection::Equirectangular => true,
+        }
+    }
+
+    /// The origin and direction of the ray through a point of the image, or `None` if the
+    /// projection does not cover it. The point is given in normalized coordinates, from -1 at the
+    /// bottom of the image to 1 at the top, and from -`aspect_ratio` to `aspect_ratio` from left
+    /// to right.
+    pub fn ray(&self, x: f64, y: f64, aspect_ratio: f64, fov: f64) -> Option<(Point, Vector3)> {
+        let forward = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        match *self {
+            Projection::Perspective => {
+                let fov_adjustment = (fov.to_radians() / 2.0).tan();
+                let direction = Vector3 {
+                    x: x * fov_adjustment,
+                    y: y * fov_adjustment,
+                    z: -1.0,
+                };
+                Some((Point::zero(), direction.normalize()))
+            }
+            Projection::Orthographic { height } => {
+                let origin = Point {
+                    x: x * height / 2.0,
+                    y: y * height / 2.0,
+                    z: 0.0,
+                };
+                Some((origin, forward))
+            }
+            Projection::Fisheye => {
+                let radius = (x * x + y * y).sqrt();
+                // Outside the image circle, the angle from the view axis is beyond fov / 2.
+                if radius > 1.0 {
+                    return None;
+                }
+                let theta = radius * fov.to_radians() / 2.0;
+                if radius == 0.0 {
+                    return Some((Point::zero(), forward));
+                }
+                let direction = Vector3 {
+                    x: theta.sin() * x / radius,
+                    y: theta.sin() * y / radius,
+                    z: -theta.cos(),
+                };
+                Some((Point::zero(), direction))
+            }
+            Projection::Equirectangular => {
+                let longitude = x / aspect_ratio * PI;
+                let latitude = y * PI / 2.0;
+                let direction = Vector3 {
+                    x: latitude.cos() * longitude.sin(),
+                    y: latitude.sin(),
+                    z: -latitude.cos() * longitude.cos(),
+                };
+                Some((Point::zero(), direction))
+            }
+        }
+    }
+}
+
+/// Renders the image as a side-by-side stereo pair, with the left eye's view in the left half of
+/// the image and the right eye's view in the right half.
+///
+/// The eyes look in parallel directions, except in equirectangular panoramas, where every column
+/// is seen by a pair of eyes turned to face it so that the depth is correct all around.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct Stereo {
+    /// The distance between the eyes.
+    pub eye_separation: f64,
+}
+impl Stereo {
+    /// Where an eye sits relative to the camera when looking in the given direction; `left`
+    /// selects the eye.
+    pub fn eye_offset(&self,
+                      projection: &Projection,
+                      direction: &Vector3,
+                      left: bool)
+                      -> Vector3 {
+        let sideways = Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        // Straight up and down, the eyes keep the orientation they have when looking ahead.
+        let right = match *projection {
+            Projection::Equirectangular if direction.x != 0.0 || direction.z != 0.0 => {
+                Vector3 {
+                        x: -direction.z,
+                        y: 0.0,
+                        z: direction.x,
+                    }
+                    .normalize()
+            }
+            _ => sideways,
+        };
+        let side = if left { -0.5 } else { 0.5 };
+        right * (self.eye_separation * side)
+    }
+}
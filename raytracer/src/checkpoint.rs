@@ -0,0 +1,289 @@
+use error::RenderError;
+use scene::{Color, Scene};
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use ViewBlock;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+/// A completed tile and the samples accumulated for each of its pixels.
+#[derive(Debug, Clone)]
+pub struct CheckpointTile {
+    pub tile: ViewBlock,
+    /// Number of samples taken for each pixel, row by row.
+    pub samples: Vec<u32>,
+
+    /// Sum of all samples taken for each pixel, in linear color, row by row.
+    pub accumulated: Vec<Color>,
+}
+impl CheckpointTile {
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        let index = (y * self.tile.width + x) as usize;
+        self.accumulated[index] * (1.0 / self.samples[index].max(1) as f32)
+    }
+}
+
+/// The state of a partially finished render, which can be saved to disk and resumed later.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub block: ViewBlock,
+    pub tile_size: u32,
+    /// Fingerprint of the scene being rendered.
+    pub scene_fingerprint: u64,
+    tiles: Vec<CheckpointTile>,
+    /// Index in `tiles` of each tile, by its position.
+    index: HashMap<(u32, u32), usize>,
+}
+impl Checkpoint {
+    pub fn new(block: &ViewBlock,
+               tile_size: u32,
+               scene: &Scene)
+               -> Result<Checkpoint, RenderError> {
+        Ok(Checkpoint {
+            block: *block,
+            tile_size,
+            scene_fingerprint: fingerprint(scene)?,
+            tiles: vec![],
+            index: HashMap::new(),
+        })
+    }
+
+    /// Whether this checkpoint was taken from a render of the same scene and block with the same
+    /// tiling.
+    pub fn is_compatible(&self,
+                         block: &ViewBlock,
+                         tile_size: u32,
+                         scene: &Scene)
+                         -> Result<bool, RenderError> {
+        Ok(self.block.x == block.x && self.block.y == block.y &&
+           self.block.width == block.width && self.block.height == block.height &&
+           self.tile_size == tile_size && self.scene_fingerprint == fingerprint(scene)?)
+    }
+
+    pub fn tiles(&self) -> &[CheckpointTile] {
+        &self.tiles
+    }
+
+    pub fn get(&self, tile: &ViewBlock) -> Option<&CheckpointTile> {
+        self.index.get(&(tile.x, tile.y)).map(|&i| &self.tiles[i])
+    }
+
+    /// Records a tile, replacing any previous record of the same tile.
+    pub fn record(&mut self, tile: CheckpointTile) {
+        match self.index.get(&(tile.tile.x, tile.tile.y)) {
+            Some(&i) => self.tiles[i] = tile,
+            None => {
+                self.index.insert((tile.tile.x, tile.tile.y), self.tiles.len());
+                self.tiles.push(tile);
+            }
+        }
+    }
+
+    /// The average color of every pixel of the block, row by row. Pixels of tiles that have not
+    /// been rendered yet are black.
+    pub fn colors(&self) -> Vec<Color> {
+        let black = Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        };
+        self.pixels(black, |done, x, y| done.color(x, y))
+    }
+
+    /// The number of samples taken for every pixel of the block, row by row. Pixels of tiles
+    /// that have not been rendered yet have none.
+    pub fn samples(&self) -> Vec<u32> {
+        self.pixels(0, |done, x, y| done.samples[(y * done.tile.width + x) as usize])
+    }
+
+    fn pixels<T, F>(&self, empty: T, value: F) -> Vec<T>
+        where T: Clone,
+              F: Fn(&CheckpointTile, u32, u32) -> T
+    {
+        let mut pixels = vec![empty; (self.block.width * self.block.height) as usize];
+        for done in &self.tiles {
+            let tile = &done.tile;
+            for y in 0..tile.height {
+                for x in 0..tile.width {
+                    let index = (y + tile.y - self.block.y) * self.block.width + x + tile.x -
+                                self.block.x;
+                    pixels[index as usize] = value(done, x, y);
+                }
+            }
+        }
+        pixels
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a render checkpoint"));
+        }
+        if read_u32(&mut reader)? != VERSION {
+            return Err(invalid_data("unsupported checkpoint version"));
+        }
+        let scene_fingerprint = read_u64(&mut reader)?;
+
+        let block = read_block(&mut reader)?;
+        let tile_size = read_u32(&mut reader)?;
+        let max_tiles = max_tiles(&block, tile_size)
+            .ok_or_else(|| invalid_data("invalid checkpoint block or tile size"))?;
+        let tile_count = read_u32(&mut reader)?;
+        if tile_count as u64 > max_tiles {
+            return Err(invalid_data("more checkpoint tiles than the block holds"));
+        }
+        // The vectors grow as the data is read rather than from the sizes given in the file, so
+        // that a truncated or corrupt file cannot make us allocate more than it holds.
+        let mut checkpoint = Checkpoint {
+            block,
+            tile_size,
+            scene_fingerprint,
+            tiles: vec![],
+            index: HashMap::new(),
+        };
+        for _ in 0..tile_count {
+            let tile = read_block(&mut reader)?;
+            if !is_on_grid(&tile, &block, tile_size) {
+                return Err(invalid_data("checkpoint tile does not match the tiling of the block"));
+            }
+            let pixels = tile.width * tile.height;
+            let mut samples = vec![];
+            for _ in 0..pixels {
+                samples.push(read_u32(&mut reader)?);
+            }
+            let mut accumulated = vec![];
+            for _ in 0..pixels {
+                accumulated.push(Color {
+                    red: read_f32(&mut reader)?,
+                    green: read_f32(&mut reader)?,
+                    blue: read_f32(&mut reader)?,
+                });
+            }
+            checkpoint.record(CheckpointTile {
+                tile,
+                samples,
+                accumulated,
+            });
+        }
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint to a temporary file next to `path` and then moves it into place,
+    /// so that a crash while saving never leaves a truncated checkpoint behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let temp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            writer.write_all(MAGIC)?;
+            write_u32(&mut writer, VERSION)?;
+            write_u64(&mut writer, self.scene_fingerprint)?;
+            write_block(&mut writer, &self.block)?;
+            write_u32(&mut writer, self.tile_size)?;
+            write_u32(&mut writer, self.tiles.len() as u32)?;
+            for tile in &self.tiles {
+                write_block(&mut writer, &tile.tile)?;
+                for &samples in &tile.samples {
+                    write_u32(&mut writer, samples)?;
+                }
+                for color in &tile.accumulated {
+                    write_f32(&mut writer, color.red)?;
+                    write_f32(&mut writer, color.green)?;
+                    write_f32(&mut writer, color.blue)?;
+                }
+            }
+            writer.flush()?;
+        }
+        fs::rename(temp_path, path)
+    }
+}
+
+/// A hash of the serialized scene, which changes with anything that changes the render.
+pub fn fingerprint(scene: &Scene) -> Result<u64, RenderError> {
+    let json = serde_json::to_vec(scene)
+        .map_err(|e| RenderError::UnserializableScene(e.to_string()))?;
+    // FNV-1a, which unlike the standard library's hasher is stable across Rust versions.
+    Ok(json.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    }))
+}
+
+/// The number of tiles a block is split into, or `None` if the block or tiling is unusable.
+fn max_tiles(block: &ViewBlock, tile_size: u32) -> Option<u64> {
+    block.width.checked_mul(block.height)?;
+    block.x.checked_add(block.width)?;
+    block.y.checked_add(block.height)?;
+    if tile_size == 0 {
+        return None;
+    }
+    let across = (block.width as u64).div_ceil(tile_size as u64);
+    let down = (block.height as u64).div_ceil(tile_size as u64);
+    Some(across * down)
+}
+
+/// Whether the tile is the one `tiles` splits the block into at its position.
+fn is_on_grid(tile: &ViewBlock, block: &ViewBlock, tile_size: u32) -> bool {
+    // Offset and size of the tile along one axis, given the start and size of the block.
+    let fits = |start: u32, size: u32, block_start: u32, block_size: u32| {
+        start.checked_sub(block_start).is_some_and(|offset| {
+            offset < block_size && offset % tile_size == 0 &&
+            size == tile_size.min(block_size - offset)
+        })
+    };
+    fits(tile.x, tile.width, block.x, block.width) &&
+    fits(tile.y, tile.height, block.y, block.height)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    read_u32(reader).map(f32::from_bits)
+}
+
+fn read_block<R: Read>(reader: &mut R) -> io::Result<ViewBlock> {
+    Ok(ViewBlock {
+        x: read_u32(reader)?,
+        y: read_u32(reader)?,
+        width: read_u32(reader)?,
+        height: read_u32(reader)?,
+    })
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32<W: Write>(writer: &mut W, value: f32) -> io::Result<()> {
+    write_u32(writer, value.to_bits())
+}
+
+fn write_block<W: Write>(writer: &mut W, block: &ViewBlock) -> io::Result<()> {
+    write_u32(writer, block.x)?;
+    write_u32(writer, block.y)?;
+    write_u32(writer, block.width)?;
+    write_u32(writer, block.height)
+}
@@ -0,0 +1,136 @@
+use aov::Aovs;
+use error::RenderError;
+use scene::Color;
+
+/// Settings for the joint bilateral denoiser. Each sigma controls how quickly a neighbour's
+/// weight falls off as it differs from the center pixel in that respect.
+#[derive(Debug, Clone)]
+pub struct DenoiseSettings {
+    /// Half-width of the filter window, in pixels.
+    pub radius: u32,
+    pub sigma_spatial: f32,
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+    /// Depth tolerance, relative to the depth of the center pixel.
+    pub sigma_depth: f32,
+}
+impl Default for DenoiseSettings {
+    fn default() -> DenoiseSettings {
+        DenoiseSettings {
+            radius: 3,
+            sigma_spatial: 2.0,
+            sigma_color: 0.3,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+const MIN_ALBEDO: f32 = 1e-3;
+
+fn color_distance2(a: Color, b: Color) -> f32 {
+    let dr = a.red - b.red;
+    let dg = a.green - b.green;
+    let db = a.blue - b.blue;
+    dr * dr + dg * dg + db * db
+}
+
+/// Divides the albedo out of a color, leaving only the lighting so that filtering does not blur
+/// textures. Channels with (almost) no albedo are left as they are.
+fn demodulate(color: Color, albedo: Color) -> Color {
+    let divide = |c: f32, a: f32| if a > MIN_ALBEDO { c / a } else { c };
+    Color {
+        red: divide(color.red, albedo.red),
+        green: divide(color.green, albedo.green),
+        blue: divide(color.blue, albedo.blue),
+    }
+}
+
+fn remodulate(lighting: Color, albedo: Color) -> Color {
+    let multiply = |c: f32, a: f32| if a > MIN_ALBEDO { c * a } else { c };
+    Color {
+        red: multiply(lighting.red, albedo.red),
+        green: multiply(lighting.green, albedo.green),
+        blue: multiply(lighting.blue, albedo.blue),
+    }
+}
+
+/// Denoises a beauty pass with a joint bilateral filter guided by the normal, albedo and depth
+/// AOVs, so that noise is smoothed out without blurring across geometric or texture edges.
+/// `beauty` holds linear colors row by row, for the same block as `guides`.
+pub fn denoise(beauty: &[Color],
+               guides: &Aovs,
+               settings: &DenoiseSettings)
+               -> Result<Vec<Color>, RenderError> {
+    let width = guides.width as i64;
+    let height = guides.height as i64;
+    if beauty.len() as i64 != width * height {
+        return Err(RenderError::SizeMismatch {
+            expected: (width * height) as usize,
+            actual: beauty.len(),
+        });
+    }
+
+    let lighting: Vec<Color> = beauty.iter()
+        .zip(guides.albedo.iter())
+        .map(|(c, a)| demodulate(*c, *a))
+        .collect();
+
+    let radius = settings.radius as i64;
+    let inv_spatial = -0.5 / (settings.sigma_spatial * settings.sigma_spatial);
+    let inv_color = -0.5 / (settings.sigma_color * settings.sigma_color);
+    let inv_normal = -0.5 / (settings.sigma_normal * settings.sigma_normal);
+    let inv_albedo = -0.5 / (settings.sigma_albedo * settings.sigma_albedo);
+
+    let mut output = Vec::with_capacity(beauty.len());
+    for y in 0..height {
+        for x in 0..width {
+            let center = (y * width + x) as usize;
+            let center_depth = guides.depth[center];
+            let mut sum = Color {
+                red: 0.0,
+                green: 0.0,
+                blue: 0.0,
+            };
+            let mut total_weight = 0.0;
+
+            for ny in (y - radius).max(0)..(y + radius + 1).min(height) {
+                for nx in (x - radius).max(0)..(x + radius + 1).min(width) {
+                    let neighbour = (ny * width + nx) as usize;
+                    let depth_weight = match (center_depth, guides.depth[neighbour]) {
+                        (Some(dc), Some(dn)) => {
+                            let sigma = settings.sigma_depth * dc as f32;
+                            let diff = (dc - dn) as f32;
+                            (-0.5 * diff * diff / (sigma * sigma).max(1e-12)).exp()
+                        }
+                        (None, None) => 1.0,
+                        // Never mix the background with geometry.
+                        _ => continue,
+                    };
+
+                    let dx = (nx - x) as f32;
+                    let dy = (ny - y) as f32;
+                    let normal_diff = 1.0 -
+                                      guides.normal[center].dot(&guides.normal[neighbour]) as f32;
+                    let exponent = (dx * dx + dy * dy) * inv_spatial +
+                                   color_distance2(lighting[center], lighting[neighbour]) *
+                                   inv_color +
+                                   normal_diff * normal_diff * inv_normal +
+                                   color_distance2(guides.albedo[center],
+                                                   guides.albedo[neighbour]) *
+                                   inv_albedo;
+                    let weight = exponent.exp() * depth_weight;
+
+                    sum = sum + lighting[neighbour] * weight;
+                    total_weight += weight;
+                }
+            }
+
+            // The center pixel always contributes with weight 1, so total_weight is never zero.
+            output.push(remodulate(sum * (1.0 / total_weight), guides.albedo[center]));
+        }
+    }
+    Ok(output)
+}
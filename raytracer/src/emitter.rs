@@ -0,0 +1,141 @@
+use instance::Transform;
+use point::Point;
+use scene::{Color, Element, Scene};
+use vector::Vector3;
+use std::f64::consts::PI;
+
+/// The shape of an emitter, in world space.
+#[derive(Debug, Clone)]
+pub enum EmitterShape {
+    Sphere { center: Point, radius: f64 },
+    Triangle([Point; 3]),
+}
+
+/// An emissive sphere or triangle, which lights the other surfaces of the scene like a light
+/// source. Emissive planes and SDF elements glow but light nothing, as they have no finite area
+/// to light from.
+#[derive(Debug, Clone)]
+pub struct Emitter {
+    pub shape: EmitterShape,
+    /// The radiance given off by the surface.
+    pub radiance: Color,
+    /// Index in `Scene::elements` of the emissive element, or of the instance it is part of.
+    pub index: usize,
+    pub instanced: bool,
+}
+
+/// The light of an emitter as seen from a point.
+#[derive(Debug, Clone, Copy)]
+pub struct EmitterView {
+    /// Direction from the point towards the emitter.
+    pub direction: Vector3,
+    /// Distance from the point to the emitter's surface along `direction`.
+    pub distance: f64,
+    /// The solid angle the emitter covers.
+    pub solid_angle: f64,
+}
+
+impl Emitter {
+    /// How the emitter is seen from a point, or `None` if the point is inside or on it.
+    pub fn view(&self, point: &Point) -> Option<EmitterView> {
+        match self.shape {
+            EmitterShape::Sphere { center, radius } => {
+                // A uniformly glowing sphere lights a point like a point light at its center,
+                // with an intensity of its radiance times the solid angle of the cone it fills.
+                let to_center = center - *point;
+                let distance = to_center.length();
+                if distance <= radius {
+                    return None;
+                }
+                Some(EmitterView {
+                    direction: to_center.normalize(),
+                    distance: distance - radius,
+                    solid_angle: 2.0 * PI * (1.0 - (1.0 - (radius / distance).powi(2)).sqrt()),
+                })
+            }
+            EmitterShape::Triangle(vertices) => {
+                let solid_angle = triangle_solid_angle(&vertices, point);
+                if solid_angle.is_nan() || solid_angle <= 0.0 {
+                    return None;
+                }
+                let centroid = Point {
+                    x: (vertices[0].x + vertices[1].x + vertices[2].x) / 3.0,
+                    y: (vertices[0].y + vertices[1].y + vertices[2].y) / 3.0,
+                    z: (vertices[0].z + vertices[1].z + vertices[2].z) / 3.0,
+                };
+                let to_centroid = centroid - *point;
+                Some(EmitterView {
+                    direction: to_centroid.normalize(),
+                    distance: to_centroid.length(),
+                    solid_angle,
+                })
+            }
+        }
+    }
+}
+
+/// The solid angle of a triangle seen from a point (Van Oosterom and Strackee).
+fn triangle_solid_angle(vertices: &[Point; 3], point: &Point) -> f64 {
+    let a = vertices[0] - *point;
+    let b = vertices[1] - *point;
+    let c = vertices[2] - *point;
+    let (la, lb, lc) = (a.length(), b.length(), c.length());
+    let numerator = a.dot(&b.cross(&c)).abs();
+    let denominator = la * lb * lc + a.dot(&b) * lc + a.dot(&c) * lb + b.dot(&c) * la;
+    2.0 * numerator.atan2(denominator)
+}
+
+/// The emitters of a scene, including those inside instances.
+pub fn emitters<'a>(scene: &'a Scene) -> impl Iterator<Item = Emitter> + 'a {
+    let elements = scene.elements.iter().enumerate();
+    let top_level = elements.clone().filter_map(|(index, element)| match *element {
+        Element::Instance(_) => None,
+        _ => emitter(element, index, None),
+    });
+    let instanced = elements.filter_map(move |(index, element)| match *element {
+            Element::Instance(ref instance) => {
+                scene.groups.get(&instance.group).map(|group| (index, instance, group))
+            }
+            _ => None,
+        })
+        .flat_map(|(index, instance, group)| {
+            group.emissive().iter().filter_map(move |&member| {
+                emitter(&group.elements[member], index, Some(&instance.transform))
+            })
+        });
+    top_level.chain(instanced)
+}
+
+fn emitter(element: &Element, index: usize, transform: Option<&Transform>) -> Option<Emitter> {
+    let material = element.material()?;
+    if !material.is_emissive() {
+        return None;
+    }
+    let to_world = |p: &Point| transform.map_or(*p, |t| t.point_to_world(p));
+    let shape = match *element {
+        Element::Sphere(ref s) => {
+            let center = to_world(&s.center);
+            // Instances scaled unevenly turn spheres into ellipsoids, which are approximated by
+            // a sphere of the average radius.
+            let axes = [Vector3::new(1.0, 0.0, 0.0),
+                        Vector3::new(0.0, 1.0, 0.0),
+                        Vector3::new(0.0, 0.0, 1.0)];
+            let radius = axes.iter()
+                .map(|axis| (to_world(&(s.center + *axis * s.radius)) - center).length())
+                .sum::<f64>() / 3.0;
+            EmitterShape::Sphere { center, radius }
+        }
+        Element::Triangle(ref t) => {
+            EmitterShape::Triangle([to_world(&t.vertices[0]),
+                                    to_world(&t.vertices[1]),
+                                    to_world(&t.vertices[2])])
+        }
+        _ => return None,
+    };
+    Some(Emitter {
+        shape,
+        radiance: material.emitted(),
+        index,
+        instanced: transform.is_some(),
+    })
+}
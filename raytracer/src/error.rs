@@ -0,0 +1,101 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a scene could not be rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderError {
+    /// The camera only supports images at least as wide as they are tall.
+    UnsupportedAspectRatio { width: u32, height: u32 },
+    /// The field of view is outside the range the projection supports, which for perspective
+    /// projections is strictly between 0 and 180 degrees.
+    InvalidFieldOfView(f64),
+    /// An instance refers to a group the scene does not define.
+    UnknownGroup(String),
+    /// A group contains an instance; groups can only contain plain elements.
+    NestedInstance(String),
+    /// An instance of the named group has a transform that cannot be inverted, such as a scale of
+    /// 0 along some axis.
+    SingularTransform(String),
+    /// A ray hit something at a distance that is not a finite number, which usually comes from
+    /// degenerate geometry such as a zero-length plane normal.
+    NonFiniteDistance(f64),
+    /// An instance was hit as if it were an element of its own, instead of through its group.
+    UnresolvedInstance,
+    /// A dispersive material has no usable index of refraction at one of the wavelengths traced
+    /// in spectral mode, given in nanometers.
+    InvalidDispersion(f32),
+    /// A material has an opacity that is not a number between 0 and 1.
+    InvalidOpacity(f32),
+    /// The checkpoint was made for a different block, tile size or scene.
+    IncompatibleCheckpoint,
+    /// The scene could not be serialized to fingerprint it for a checkpoint.
+    UnserializableScene(String),
+    /// A buffer or image does not have the size of the block being rendered.
+    SizeMismatch { expected: usize, actual: usize },
+    /// An image is too small for the block rendered into it.
+    ImageTooSmall {
+        block: (u32, u32),
+        image: (u32, u32),
+    },
+    /// The scene has no pixels to render.
+    EmptyImage { width: u32, height: u32 },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::UnsupportedAspectRatio { width, height } => {
+                write!(f,
+                       "Scene is {}x{}, but images taller than they are wide are not supported",
+                       width,
+                       height)
+            }
+            RenderError::InvalidFieldOfView(fov) => {
+                write!(f, "Field of view of {} degrees is not supported by the projection", fov)
+            }
+            RenderError::UnknownGroup(ref name) => write!(f, "No group named \"{}\"", name),
+            RenderError::NestedInstance(ref name) => {
+                write!(f, "Group \"{}\" contains an instance", name)
+            }
+            RenderError::SingularTransform(ref name) => {
+                write!(f,
+                       "An instance of group \"{}\" has a transform that cannot be inverted",
+                       name)
+            }
+            RenderError::NonFiniteDistance(distance) => {
+                write!(f, "Ray hit an element at a non-finite distance ({})", distance)
+            }
+            RenderError::UnresolvedInstance => {
+                write!(f, "An instance was hit without going through its group")
+            }
+            RenderError::InvalidDispersion(wavelength) => {
+                write!(f, "Dispersion gives no valid index of refraction at {} nm", wavelength)
+            }
+            RenderError::InvalidOpacity(opacity) => {
+                write!(f, "Opacity {} is not between 0 and 1", opacity)
+            }
+            RenderError::IncompatibleCheckpoint => {
+                write!(f, "Checkpoint does not match the scene or block being rendered")
+            }
+            RenderError::UnserializableScene(ref message) => {
+                write!(f, "Unable to serialize the scene: {}", message)
+            }
+            RenderError::SizeMismatch { expected, actual } => {
+                write!(f, "Expected {} pixels, got {}", expected, actual)
+            }
+            RenderError::ImageTooSmall { block, image } => {
+                write!(f,
+                       "Image of {}x{} pixels cannot hold a block of {}x{}",
+                       image.0,
+                       image.1,
+                       block.0,
+                       block.1)
+            }
+            RenderError::EmptyImage { width, height } => {
+                write!(f, "Cannot render an image of {}x{} pixels", width, height)
+            }
+        }
+    }
+}
+
+impl Error for RenderError {}
@@ -0,0 +1,167 @@
+use bvh::{BoundingBox, Bvh};
+use matrix::Matrix44;
+use packet::{PACKET_SIZE, RayPacket};
+use point::Point;
+use rendering::Ray;
+use scene::{Element, Material};
+use vector::Vector3;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+
+/// One step of a transform. Angles are in degrees.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum TransformStep {
+    Scale { x: f64, y: f64, z: f64 },
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Translate { x: f64, y: f64, z: f64 },
+    /// A raw matrix in the row-vector convention of `Matrix44`.
+    Matrix([[f64; 4]; 4]),
+}
+impl TransformStep {
+    pub fn matrix(&self) -> Matrix44 {
+        match *self {
+            TransformStep::Scale { x, y, z } => Matrix44::scale(x, y, z),
+            TransformStep::RotateX(angle) => Matrix44::rotate_x(angle.to_radians()),
+            TransformStep::RotateY(angle) => Matrix44::rotate_y(angle.to_radians()),
+            TransformStep::RotateZ(angle) => Matrix44::rotate_z(angle.to_radians()),
+            TransformStep::Translate { x, y, z } => Matrix44::translate(x, y, z),
+            TransformStep::Matrix(elements) => Matrix44::new(elements),
+        }
+    }
+}
+
+/// A sequence of steps, applied in order, mapping object space to world space.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Transform {
+    pub steps: Vec<TransformStep>,
+
+    #[serde(skip_serializing, skip_deserializing, default = "Matrix44::identity")]
+    matrix: Matrix44,
+    #[serde(skip_serializing, skip_deserializing, default = "Matrix44::identity")]
+    inverse: Matrix44,
+}
+impl Transform {
+    pub fn new(steps: Vec<TransformStep>) -> Transform {
+        let matrix = steps.iter().fold(Matrix44::identity(), |m, step| m * step.matrix());
+        Transform {
+            steps,
+            matrix,
+            inverse: matrix.inverse(),
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix44 {
+        &self.matrix
+    }
+
+    pub fn point_to_object(&self, point: &Point) -> Point {
+        *point * self.inverse
+    }
+
+    pub fn point_to_world(&self, point: &Point) -> Point {
+        *point * self.matrix
+    }
+
+    /// Transforms an object-space normal to world space, using the inverse transpose so that it
+    /// stays perpendicular to the surface under non-uniform scaling.
+    pub fn normal_to_world(&self, normal: &Vector3) -> Vector3 {
+        (self.inverse.transpose() * *normal).normalize()
+    }
+
+    /// Transforms a world-space ray to object space. The direction is normalized, so the result
+    /// also returns the factor converting object-space distances back to world-space ones.
+    pub fn ray_to_object(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.inverse * ray.direction;
+        let length = direction.length();
+        let object_ray = Ray {
+            origin: self.point_to_object(&ray.origin),
+            direction: direction * length.recip(),
+            wavelength: ray.wavelength,
+        };
+        (object_ray, length.recip())
+    }
+
+    pub fn bounds_to_world(&self, bounds: &BoundingBox) -> BoundingBox {
+        bounds.corners()
+            .iter()
+            .fold(BoundingBox::empty(), |b, corner| b.include(&self.point_to_world(corner)))
+    }
+}
+
+fn load_transform<D>(deserializer: D) -> Result<Transform, D::Error>
+    where D: Deserializer
+{
+    let transform = Transform::deserialize(deserializer)?;
+    Ok(Transform::new(transform.steps))
+}
+
+/// Named geometry shared by any number of instances. The group's elements are stored once,
+/// along with a single acceleration structure used by all of its instances.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Group {
+    pub elements: Vec<Element>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    bvh: Bvh,
+    #[serde(skip_serializing, skip_deserializing)]
+    emissive: Vec<usize>,
+}
+impl Group {
+    pub fn new(elements: Vec<Element>) -> Group {
+        let bvh = Bvh::build(&elements);
+        let emissive = (0..elements.len())
+            .filter(|&i| elements[i].material().is_some_and(Material::is_emissive))
+            .collect();
+        Group {
+            elements,
+            bvh,
+            emissive,
+        }
+    }
+
+    /// Indices of the elements with an emissive material.
+    pub fn emissive(&self) -> &[usize] {
+        &self.emissive
+    }
+
+    /// Finds the closest element of the group hit by an object-space ray, adding the number of
+    /// elements tested to `tests`.
+    pub fn trace(&self, ray: &Ray, tests: &mut u64) -> Option<(f64, &Element)> {
+        self.bvh.trace(&self.elements, ray, tests).map(|(d, i)| (d, &self.elements[i]))
+    }
+
+    /// Like `trace`, for every ray of an object-space packet.
+    pub fn trace_packet(&self,
+                        packet: &RayPacket,
+                        tests: &mut u64)
+                        -> [Option<(f64, &Element)>; PACKET_SIZE] {
+        let hits = self.bvh.trace_packet(&self.elements, packet, tests);
+        ::std::array::from_fn(|i| hits[i].map(|(d, index)| (d, &self.elements[index])))
+    }
+
+    /// The bounds of the group in object space, or `None` if it contains infinite elements.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        if self.bvh.has_unbounded() {
+            None
+        } else {
+            self.bvh.bounds()
+        }
+    }
+}
+
+pub fn load_groups<D>(deserializer: D) -> Result<HashMap<String, Group>, D::Error>
+    where D: Deserializer
+{
+    let groups = HashMap::<String, Group>::deserialize(deserializer)?;
+    Ok(groups.into_iter().map(|(name, group)| (name, Group::new(group.elements))).collect())
+}
+
+/// Places a copy of a named group in the scene. Groups cannot contain instances themselves.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Instance {
+    pub group: String,
+    #[serde(deserialize_with = "load_transform")]
+    pub transform: Transform,
+}
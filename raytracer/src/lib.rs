@@ -2,20 +2,47 @@
 extern crate serde_derive;
 extern crate image;
 extern crate serde;
+extern crate serde_json;
 
 pub mod scene;
+pub mod builder;
+pub mod background;
+pub mod media;
+pub mod spectral;
 pub mod vector;
 pub mod point;
-mod rendering;
-mod matrix;
+pub mod progress;
+pub mod checkpoint;
+pub mod aov;
+pub mod denoise;
+pub mod bvh;
+pub mod instance;
+pub mod sdf;
+pub mod photon;
+pub mod light_sampling;
+pub mod emitter;
+pub mod sampling;
+pub mod stats;
+pub mod camera;
+pub mod error;
+pub mod packet;
+pub mod rendering;
+pub mod matrix;
 
-use scene::Scene;
+use scene::{Color, Scene};
 use image::{DynamicImage, GenericImage, ImageBuffer, Rgba};
+use progress::Progress;
+use checkpoint::{Checkpoint, CheckpointTile};
+use std::cmp;
+use stats::TileStats;
+use std::time::Instant;
 
-use rendering::{Ray, cast_ray};
+use packet::PACKET_SIZE;
+use rendering::trace_packet;
+use error::RenderError;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ViewBlock {
     pub x: u32,
     pub y: u32,
@@ -23,13 +50,158 @@ pub struct ViewBlock {
     pub height: u32,
 }
 
-pub fn render(block: &ViewBlock, scene: &Scene) -> DynamicImage {
+pub const DEFAULT_TILE_SIZE: u32 = 32;
+
+pub fn render(block: &ViewBlock, scene: &Scene) -> Result<DynamicImage, RenderError> {
+    render_with_progress(block, scene, DEFAULT_TILE_SIZE, |_, _| {})
+}
+
+/// Splits the block into tiles of at most `tile_size` pixels square, in scanline order.
+pub fn tiles(block: &ViewBlock, tile_size: u32) -> Vec<ViewBlock> {
+    let tile_size = cmp::max(tile_size, 1);
+    let mut tiles = vec![];
+    let mut y = 0;
+    while y < block.height {
+        let mut x = 0;
+        while x < block.width {
+            tiles.push(ViewBlock {
+                x: block.x + x,
+                y: block.y + y,
+                width: cmp::min(tile_size, block.width - x),
+                height: cmp::min(tile_size, block.height - y),
+            });
+            x += tile_size;
+        }
+        y += tile_size;
+    }
+    tiles
+}
+
+/// Renders the block tile by tile, calling `report` with the progress so far and the partially
+/// rendered image after each tile. Pixels that have not been rendered yet are black.
+pub fn render_with_progress<F>(block: &ViewBlock,
+                               scene: &Scene,
+                               tile_size: u32,
+                               mut report: F)
+                               -> Result<DynamicImage, RenderError>
+    where F: FnMut(&Progress, &DynamicImage)
+{
+    let mut checkpoint = Checkpoint::new(block, tile_size, scene)?;
+    render_resumable(block,
+                     scene,
+                     &mut checkpoint,
+                     |progress, image, _| report(progress, image))
+}
+
+/// Like `render_with_progress`, but skips the tiles already recorded in the checkpoint and
+/// records every newly rendered tile into it, so that the render can be resumed later if it is
+/// interrupted. The checkpoint determines the tile size and must match the scene and block.
+pub fn render_resumable<F>(block: &ViewBlock,
+                           scene: &Scene,
+                           checkpoint: &mut Checkpoint,
+                           mut report: F)
+                           -> Result<DynamicImage, RenderError>
+    where F: FnMut(&Progress, &DynamicImage, &Checkpoint)
+{
+    scene.validate()?;
+    if !checkpoint.is_compatible(block, checkpoint.tile_size, scene)? {
+        return Err(RenderError::IncompatibleCheckpoint);
+    }
     let mut image = DynamicImage::new_rgb8(block.width, block.height);
-    for y in 0..block.height {
-        for x in 0..block.width {
-            let ray = Ray::create_prime(x + block.x, y + block.y, scene);
-            image.put_pixel(x, y, cast_ray(scene, &ray, 0).to_rgba());
-            println!("y: {}, x : {}", y, x);
+    for done in checkpoint.tiles() {
+        write_tile(block, done, &mut image);
+    }
+
+    // Shoot the photons up front, so that they do not skew the time of the first tile.
+    if let Some(ref caustics) = scene.caustics {
+        caustics.photon_map(scene)?;
+    }
+
+    let tiles = tiles(block, checkpoint.tile_size);
+    let resumed_tiles = tiles.iter().filter(|t| checkpoint.get(t).is_some()).count() as u32;
+    let mut completed_tiles = resumed_tiles;
+    let start = Instant::now();
+    for tile in &tiles {
+        if checkpoint.get(tile).is_some() {
+            continue;
+        }
+        let tile_start = Instant::now();
+        let (done, counts) = stats::measure(|| render_tile(tile, scene));
+        let done = done?;
+        let stats = TileStats {
+            tile: *tile,
+            time: tile_start.elapsed(),
+            counts,
+        };
+        write_tile(block, &done, &mut image);
+        checkpoint.record(done);
+        completed_tiles += 1;
+
+        let progress = Progress {
+            completed_tiles,
+            resumed_tiles,
+            total_tiles: tiles.len() as u32,
+            elapsed: start.elapsed(),
+            tile: *tile,
+            stats,
+        };
+        report(&progress, &image, checkpoint);
+    }
+    Ok(image)
+}
+
+fn render_tile(tile: &ViewBlock, scene: &Scene) -> Result<CheckpointTile, RenderError> {
+    if let Some(ref adaptive) = scene.adaptive_sampling {
+        return adaptive.render_tile(tile, scene);
+    }
+    Ok(CheckpointTile {
+        tile: *tile,
+        samples: vec![1; (tile.width * tile.height) as usize],
+        accumulated: trace_block(tile, scene)?,
+    })
+}
+
+/// Traces every pixel of the block, returning their colors row by row. Pixels are traced in
+/// 2x2 quads so that the rays of each packet are coherent.
+fn trace_block(block: &ViewBlock, scene: &Scene) -> Result<Vec<Color>, RenderError> {
+    let mut colors = vec![Color { red: 0.0, green: 0.0, blue: 0.0 };
+                          (block.width * block.height) as usize];
+    let mut quad = [(0, 0); PACKET_SIZE];
+    for y in (0..block.height).step_by(2) {
+        for x in (0..block.width).step_by(2) {
+            let mut count = 0;
+            for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                if x + dx < block.width && y + dy < block.height {
+                    quad[count] = (x + dx + block.x, y + dy + block.y);
+                    count += 1;
+                }
+            }
+            let quad = &quad[..count];
+            for (&(x, y), color) in quad.iter().zip(trace_packet(scene, quad)?.iter()) {
+                colors[((y - block.y) * block.width + x - block.x) as usize] = *color;
+            }
+        }
+    }
+    Ok(colors)
+}
+
+fn write_tile(block: &ViewBlock, done: &CheckpointTile, image: &mut DynamicImage) {
+    let tile = &done.tile;
+    for y in 0..tile.height {
+        for x in 0..tile.width {
+            image.put_pixel(x + tile.x - block.x,
+                            y + tile.y - block.y,
+                            done.color(x, y).to_rgba());
+        }
+    }
+}
+
+/// Converts linear colors, stored row by row, to an image.
+pub fn colors_to_image(width: u32, height: u32, colors: &[Color]) -> DynamicImage {
+    let mut image = DynamicImage::new_rgb8(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            image.put_pixel(x, y, colors[(y * width + x) as usize].to_rgba());
         }
     }
     image
@@ -37,11 +209,19 @@ pub fn render(block: &ViewBlock, scene: &Scene) -> DynamicImage {
 
 pub fn render_into(block: &ViewBlock,
     scene: &Scene,
-    image: &mut ImageBuffer<Rgba<u8>, &mut [u8]>) {
+    image: &mut ImageBuffer<Rgba<u8>, &mut [u8]>) -> Result<(), RenderError> {
+    scene.validate()?;
+    if image.width() < block.width || image.height() < block.height {
+        return Err(RenderError::ImageTooSmall {
+            block: (block.width, block.height),
+            image: (image.width(), image.height()),
+        });
+    }
+    let colors = trace_block(block, scene)?;
     for y in 0..block.height {
         for x in 0..block.width {
-            let ray = Ray::create_prime(x + block.x, y + block.y, scene);
-            image.put_pixel(x, y, cast_ray(scene, &ray, 0).to_rgba());
+            image.put_pixel(x, y, colors[(y * block.width + x) as usize].to_rgba());
         }
     }
+    Ok(())
 }
@@ -0,0 +1,363 @@
+use bvh::BoundingBox;
+use photon::random;
+use point::Point;
+use scene::{Light, Scene};
+use vector::Vector3;
+use std::f32::consts::PI;
+use std::iter::Chain;
+use std::slice;
+use std::sync::OnceLock;
+
+fn default_samples() -> u32 {
+    1
+}
+
+/// Shades each point with a few lights picked at random instead of with every light, which keeps
+/// the cost of shading independent of the number of lights. Lights are picked in proportion to
+/// their power, from a table built once for the scene, and their light is scaled up to make up
+/// for the lights that were left out. The picks are random but the same for every render of the
+/// scene, and the noise they cause averages out as more samples are taken with adaptive sampling.
+///
+/// Light from the background and the light scattered by fog and volumes are sampled the same way.
+/// Emissive elements are always used.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LightSampling {
+    /// Number of lights picked for each shaded point. The same light may be picked more than once.
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+    /// Picks lights by walking down a tree of the positions of the point and spot lights, in
+    /// proportion to their estimated contribution to the shaded point, from their intensity,
+    /// distance and angle. This takes more work per pick than the power table, but wastes fewer
+    /// picks on lights that are far away or behind the surface. Directional lights and the
+    /// background are still picked from a power table of their own.
+    #[serde(default)]
+    pub tree: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    light_tree: OnceLock<LightTree>,
+    #[serde(skip_serializing, skip_deserializing)]
+    power_table: OnceLock<PowerTable>,
+}
+impl LightSampling {
+    pub fn new(samples: u32, tree: bool) -> LightSampling {
+        LightSampling {
+            samples,
+            tree,
+            light_tree: OnceLock::new(),
+            power_table: OnceLock::new(),
+        }
+    }
+
+    /// Picks the lights to shade a point with, each with the factor to scale its light by. With
+    /// the tree, the normal of the surface at the point, if any, is used to skip the lights
+    /// behind it.
+    pub fn select<'a>(&'a self,
+                      scene: &'a Scene,
+                      point: &Point,
+                      normal: Option<&Vector3>)
+                      -> SampledLights<'a> {
+        SampledLights {
+            sampling: self,
+            scene,
+            point: *point,
+            normal: normal.cloned(),
+            seed: point_seed(point),
+            sample: 0,
+            samples: self.samples.max(1),
+        }
+    }
+}
+
+/// The lights to shade a point with, each with the factor to scale its light by.
+pub enum LightsAt<'a> {
+    All(Chain<slice::Iter<'a, Light>, slice::Iter<'a, Light>>),
+    Sampled(SampledLights<'a>),
+}
+impl<'a> Iterator for LightsAt<'a> {
+    type Item = (&'a Light, f32);
+
+    fn next(&mut self) -> Option<(&'a Light, f32)> {
+        match *self {
+            LightsAt::All(ref mut lights) => lights.next().map(|light| (light, 1.0)),
+            LightsAt::Sampled(ref mut lights) => lights.next(),
+        }
+    }
+}
+
+/// The lights picked for a point, which are picked one at a time as they are used.
+pub struct SampledLights<'a> {
+    sampling: &'a LightSampling,
+    scene: &'a Scene,
+    point: Point,
+    normal: Option<Vector3>,
+    seed: u64,
+    sample: u32,
+    samples: u32,
+}
+impl<'a> Iterator for SampledLights<'a> {
+    type Item = (&'a Light, f32);
+
+    fn next(&mut self) -> Option<(&'a Light, f32)> {
+        let (sampling, scene) = (self.sampling, self.scene);
+        while self.sample < self.samples {
+            let u = random(self.seed, self.sample);
+            self.sample += 1;
+            let picked = if sampling.tree {
+                sampling.light_tree
+                    .get_or_init(|| LightTree::build(scene))
+                    .pick(scene, &self.point, self.normal.as_ref(), u)
+            } else {
+                sampling.power_table.get_or_init(|| PowerTable::build(scene)).pick(u)
+            };
+            if let Some((index, probability)) = picked {
+                return Some((light(scene, index), 1.0 / (probability * self.samples as f32)));
+            }
+        }
+        None
+    }
+}
+
+/// The lights of the scene followed by the lights standing in for the background, which are
+/// numbered in this order.
+fn all_lights(scene: &Scene) -> impl Iterator<Item = &Light> {
+    scene.lights.iter().chain(scene.background.lights())
+}
+
+fn light(scene: &Scene, index: usize) -> &Light {
+    if index < scene.lights.len() {
+        &scene.lights[index]
+    } else {
+        &scene.background.lights()[index - scene.lights.len()]
+    }
+}
+
+/// The brightness of a light, with point and spot lights taken at a distance of one unit.
+fn power(light: &Light) -> f32 {
+    let intensity = match *light {
+        Light::Directional(ref d) => d.intensity,
+        Light::Spherical(ref s) => s.intensity / (4.0 * PI),
+        Light::Spot(ref s) => s.intensity / (4.0 * PI),
+    };
+    light.color().luminance() * intensity
+}
+
+/// The running total of the power of a list of lights, by default those of a scene numbered as
+/// in `all_lights`.
+#[derive(Debug)]
+struct PowerTable {
+    cumulative: Vec<f32>,
+}
+impl PowerTable {
+    fn build(scene: &Scene) -> PowerTable {
+        PowerTable::new(all_lights(scene))
+    }
+
+    fn new<'a, I: Iterator<Item = &'a Light>>(lights: I) -> PowerTable {
+        let mut total = 0.0;
+        let cumulative = lights.map(|light| {
+                total += power(light);
+                total
+            })
+            .collect();
+        PowerTable { cumulative }
+    }
+
+    fn total(&self) -> f32 {
+        self.cumulative.last().cloned().unwrap_or(0.0)
+    }
+
+    /// Picks a light given a number in [0, 1), returning its index and the probability that it
+    /// was picked, or `None` if no light has any power.
+    fn pick(&self, u: f64) -> Option<(usize, f32)> {
+        let total = *self.cumulative.last()?;
+        if total <= 0.0 {
+            return None;
+        }
+        let target = u * total as f64;
+        let start = |i: usize| if i > 0 { self.cumulative[i - 1] } else { 0.0 };
+        let weight = |i: usize| self.cumulative[i] - start(i);
+        let mut index = self.cumulative.partition_point(|&c| c as f64 <= target);
+        // Rounding may leave the target just past the last light with any power.
+        if index == self.cumulative.len() {
+            index = (0..index).rev().find(|&i| weight(i) > 0.0)?;
+        }
+        Some((index, weight(index) / total))
+    }
+}
+
+/// The brightness of the light from `light` reaching a point, ignoring shadows.
+fn importance(light: &Light, point: &Point, normal: Option<&Vector3>) -> f32 {
+    let cosine = normal.map_or(1.0, |n| (n.dot(&light.direction_from(point)) as f32).max(0.0));
+    light.color().luminance() * light.intensity(point) * cosine
+}
+
+/// Picks an index with a probability proportional to its weight, given a number in [0, 1).
+/// Also returns the number rescaled to [0, 1) within the range of the picked index, so that it
+/// can be used for further picks.
+fn pick(weights: &[f32], total: f32, u: f64) -> (usize, f64) {
+    let target = u * total as f64;
+    let mut start = 0.0;
+    for (index, &weight) in weights.iter().enumerate() {
+        let end = start + weight as f64;
+        if target < end && weight > 0.0 {
+            return (index, ((target - start) / weight as f64).min(1.0 - f64::EPSILON));
+        }
+        start = end;
+    }
+    // Rounding may leave the target just past the last range.
+    let last = weights.iter().rposition(|&w| w > 0.0).unwrap_or(weights.len() - 1);
+    (last, 1.0 - f64::EPSILON)
+}
+
+fn point_seed(point: &Point) -> u64 {
+    point.x.to_bits() ^ point.y.to_bits().rotate_left(21) ^ point.z.to_bits().rotate_left(42)
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Light(usize),
+    Split(usize, usize),
+}
+
+/// A cluster of point and spot lights.
+#[derive(Debug)]
+struct Node {
+    bounds: BoundingBox,
+    /// Total brightness of the lights, before falling off with distance.
+    power: f32,
+    kind: NodeKind,
+}
+
+/// A binary tree over the positions of the point and spot lights of a scene. Directional lights
+/// and the lights standing in for the background have no position, and are kept aside in a power
+/// table.
+#[derive(Debug)]
+struct LightTree {
+    nodes: Vec<Node>,
+    distant: Vec<usize>,
+    /// The power of the lights in `distant`, in the same order.
+    distant_power: PowerTable,
+}
+impl LightTree {
+    fn build(scene: &Scene) -> LightTree {
+        let mut tree = LightTree {
+            nodes: vec![],
+            distant: vec![],
+            distant_power: PowerTable { cumulative: vec![] },
+        };
+        let mut local = vec![];
+        for (index, light) in all_lights(scene).enumerate() {
+            let (position, intensity) = match *light {
+                Light::Spherical(ref s) => (s.position, s.intensity),
+                Light::Spot(ref s) => (s.position, s.intensity),
+                Light::Directional(_) => {
+                    tree.distant.push(index);
+                    continue;
+                }
+            };
+            local.push((index, position, light.color().luminance() * intensity));
+        }
+        if !local.is_empty() {
+            tree.add(&mut local);
+        }
+        tree.distant_power = PowerTable::new(tree.distant.iter().map(|&i| light(scene, i)));
+        tree
+    }
+
+    /// Adds the subtree of the given lights, returning the index of its root. The root of the
+    /// whole tree is the first node.
+    fn add(&mut self, lights: &mut [(usize, Point, f32)]) -> usize {
+        let bounds = lights.iter().fold(BoundingBox::empty(), |b, l| b.include(&l.1));
+        let power = lights.iter().map(|l| l.2).sum();
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            power,
+            kind: NodeKind::Light(lights[0].0),
+        });
+        if lights.len() > 1 {
+            let extent = bounds.max - bounds.min;
+            let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            };
+            let coordinate = |p: &Point| [p.x, p.y, p.z][axis];
+            let middle = lights.len() / 2;
+            lights.select_nth_unstable_by(middle, |a, b| {
+                coordinate(&a.1).total_cmp(&coordinate(&b.1))
+            });
+            let (left, right) = lights.split_at_mut(middle);
+            let left = self.add(left);
+            let right = self.add(right);
+            self.nodes[node].kind = NodeKind::Split(left, right);
+        }
+        node
+    }
+
+    /// The estimated brightness of the light from a node reaching a point. Clusters are treated
+    /// as a single light at their center, no closer than their size.
+    fn importance(&self,
+                  scene: &Scene,
+                  node: usize,
+                  point: &Point,
+                  normal: Option<&Vector3>)
+                  -> f32 {
+        let node = &self.nodes[node];
+        match node.kind {
+            NodeKind::Light(index) => importance(light(scene, index), point, normal),
+            NodeKind::Split(..) => {
+                let distance2 = (node.bounds.centroid() - *point).norm();
+                let radius2 = (node.bounds.max - node.bounds.min).norm() / 4.0;
+                node.power / (4.0 * PI * distance2.max(radius2) as f32)
+            }
+        }
+    }
+
+    /// Picks a light given a number in [0, 1), returning its index and the probability that it
+    /// was picked, or `None` if no light reaches the point. The distant lights are picked as a
+    /// whole in proportion to their power, and the tree in proportion to its estimated importance.
+    fn pick(&self,
+            scene: &Scene,
+            point: &Point,
+            normal: Option<&Vector3>,
+            u: f64)
+            -> Option<(usize, f32)> {
+        let tree = if self.nodes.is_empty() {
+            0.0
+        } else {
+            self.importance(scene, 0, point, normal)
+        };
+        let groups = [self.distant_power.total(), tree];
+        let total = groups[0] + groups[1];
+        if total <= 0.0 {
+            return None;
+        }
+        let (picked, mut u) = pick(&groups, total, u);
+        let mut probability = groups[picked] / total;
+        if picked == 0 {
+            let (index, power) = self.distant_power.pick(u)?;
+            return Some((self.distant[index], probability * power));
+        }
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Light(index) => return Some((index, probability)),
+                NodeKind::Split(left, right) => {
+                    let children = [self.importance(scene, left, point, normal),
+                                    self.importance(scene, right, point, normal)];
+                    let total = children[0] + children[1];
+                    if total <= 0.0 {
+                        return None;
+                    }
+                    let (child, rescaled) = pick(&children, total, u);
+                    probability *= children[child] / total;
+                    u = rescaled;
+                    node = if child == 0 { left } else { right };
+                }
+            }
+        }
+    }
+}
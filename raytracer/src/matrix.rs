@@ -2,11 +2,17 @@ use std::ops::{Mul, Index, IndexMut};
 use point::Point;
 use vector::Vector3;
 
-#[derive(Clone, Debug)]
+/// A 4x4 matrix transforming row vectors, so that `point * a * b` applies `a` and then `b`.
+/// Translation is stored in the last row.
+#[derive(Clone, Copy, Debug)]
 pub struct Matrix44 {
     elements: [[f64; 4]; 4],
 }
 impl Matrix44 {
+    pub fn new(elements: [[f64; 4]; 4]) -> Matrix44 {
+        Matrix44 { elements }
+    }
+
     #[cfg_attr(rustfmt, rustfmt_skip)]
     pub fn identity() -> Matrix44 {
         Matrix44 {
@@ -70,16 +76,48 @@ impl Matrix44 {
     #[cfg_attr(rustfmt, rustfmt_skip)]
     pub fn translate(tx: f64, ty:f64, tz: f64) -> Matrix44 {
         Matrix44 {
-            elements: [[0.0, 0.0, 0.0,  tx],
-                       [0.0, 0.0, 0.0,  ty],
-                       [0.0, 0.0, 0.0,  tz],
-                       [0.0, 0.0, 0.0, 0.0]],
+            elements: [[1.0, 0.0, 0.0, 0.0],
+                       [0.0, 1.0, 0.0, 0.0],
+                       [0.0, 0.0, 1.0, 0.0],
+                       [ tx,  ty,  tz, 1.0]],
         }
     }
 
+    pub fn transpose(&self) -> Matrix44 {
+        let mut result = Matrix44::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                result[i][j] = self[j][i];
+            }
+        }
+        result
+    }
+
+    pub fn determinant(&self) -> f64 {
+        let m = &self.elements;
+        // Expands along the first two rows, pairing the 2x2 minors of the top rows with their
+        // complements in the bottom rows.
+        let minor = |r: usize, a: usize, b: usize| m[r][a] * m[r + 1][b] - m[r][b] * m[r + 1][a];
+        minor(0, 0, 1) * minor(2, 2, 3) - minor(0, 0, 2) * minor(2, 1, 3) +
+        minor(0, 0, 3) * minor(2, 1, 2) + minor(0, 1, 2) * minor(2, 0, 3) -
+        minor(0, 1, 3) * minor(2, 0, 2) + minor(0, 2, 3) * minor(2, 0, 1)
+    }
+
+    /// Whether the matrix can be inverted, with a determinant that is not negligible next to the
+    /// largest one its rows could have.
+    pub fn is_invertible(&self) -> bool {
+        let bound: f64 = self.elements
+            .iter()
+            .map(|row| row.iter().map(|x| x * x).sum::<f64>().sqrt())
+            .product();
+        let determinant = self.determinant();
+        determinant.is_finite() && determinant.abs() > 1e-12 * bound
+    }
+
+    /// The inverse of the matrix, or the identity if it cannot be inverted.
     pub fn inverse(&self) -> Matrix44 {
         let mut s = Matrix44::identity();
-        let mut t = self.clone();
+        let mut t = *self;
         // Forward elimination
         for i in 0..3 {
             let mut pivot = i;
@@ -0,0 +1,170 @@
+use point::Point;
+use rendering::{BLACK, Ray, shadow_transmission};
+use error::RenderError;
+use scene::{Color, Light, Scene};
+use vector::Vector3;
+use std::f32;
+
+fn white() -> Color {
+    Color {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    }
+}
+
+/// A homogeneous participating medium. Coefficients are per unit of distance.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Medium {
+    pub absorption: f32,
+    pub scattering: f32,
+    /// Tints the light scattered towards the camera.
+    #[serde(default = "white")]
+    pub color: Color,
+}
+impl Medium {
+    pub fn extinction(&self) -> f32 {
+        self.absorption + self.scattering
+    }
+}
+
+/// A medium filling the whole scene.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Fog {
+    pub medium: Medium,
+    /// How far along a ray the fog extends. Without a limit, rays that escape the scene (and
+    /// shadow rays towards directional lights) are entirely absorbed.
+    #[serde(default)]
+    pub max_distance: Option<f64>,
+}
+impl Fog {
+    fn extent(&self, t_max: f64) -> f64 {
+        self.max_distance.map_or(t_max, |d| d.min(t_max))
+    }
+}
+
+/// A sphere filled with a medium, such as a puff of smoke.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Volume {
+    pub center: Point,
+    pub radius: f64,
+    pub medium: Medium,
+}
+impl Volume {
+    /// The part of the ray between `0` and `t_max` that lies inside the volume.
+    fn interval(&self, origin: &Point, direction: &Vector3, t_max: f64) -> Option<(f64, f64)> {
+        let l = self.center - *origin;
+        let adj = l.dot(direction);
+        let d2 = l.dot(&l) - adj * adj;
+        let radius2 = self.radius * self.radius;
+        if d2 > radius2 {
+            return None;
+        }
+        let thc = (radius2 - d2).sqrt();
+        let t0 = (adj - thc).max(0.0);
+        let t1 = (adj + thc).min(t_max);
+        if t0 < t1 { Some((t0, t1)) } else { None }
+    }
+}
+
+pub fn default_volume_steps() -> u32 {
+    16
+}
+
+// Rays that escape through fog are only marched until this fraction of the light remains.
+const MIN_TRANSMITTANCE: f64 = 1e-3;
+
+const ISOTROPIC_PHASE: f32 = 1.0 / (4.0 * f32::consts::PI);
+
+fn has_media(scene: &Scene) -> bool {
+    scene.fog.is_some() || !scene.volumes.is_empty()
+}
+
+/// Total extinction along a ray from `origin`, between `0` and `t_max`.
+fn optical_depth(scene: &Scene, origin: &Point, direction: &Vector3, t_max: f64) -> f64 {
+    let mut depth = 0.0;
+    if let Some(ref fog) = scene.fog {
+        let extent = fog.extent(t_max);
+        if fog.medium.extinction() > 0.0 {
+            depth += fog.medium.extinction() as f64 * extent;
+        }
+    }
+    for volume in &scene.volumes {
+        if let Some((t0, t1)) = volume.interval(origin, direction, t_max) {
+            depth += volume.medium.extinction() as f64 * (t1 - t0);
+        }
+    }
+    depth
+}
+
+/// The fraction of light that survives the media between `origin` and `t_max` along the ray.
+pub fn transmittance(scene: &Scene, origin: &Point, direction: &Vector3, t_max: f64) -> f32 {
+    if !has_media(scene) {
+        return 1.0;
+    }
+    (-optical_depth(scene, origin, direction, t_max)).exp() as f32
+}
+
+/// The media between the ray origin and `t_max` along the ray: the fraction of the light from
+/// `t_max` that gets through them, and the light they scatter towards the ray origin (single
+/// scattering).
+pub fn along(scene: &Scene, ray: &Ray, t_max: f64) -> Result<(Color, Color), RenderError> {
+    if !has_media(scene) {
+        return Ok((white(), BLACK));
+    }
+    let transmitted = white() * transmittance(scene, &ray.origin, &ray.direction, t_max);
+    let mut result = BLACK;
+
+    if let Some(ref fog) = scene.fog {
+        let extinction = fog.medium.extinction() as f64;
+        if extinction > 0.0 {
+            let end = fog.extent(t_max).min(-MIN_TRANSMITTANCE.ln() / extinction);
+            result = result + in_scattering(scene, ray, &fog.medium, 0.0, end)?;
+        }
+    }
+    for volume in &scene.volumes {
+        if let Some((t0, t1)) = volume.interval(&ray.origin, &ray.direction, t_max) {
+            result = result + in_scattering(scene, ray, &volume.medium, t0, t1)?;
+        }
+    }
+    Ok((transmitted, result))
+}
+
+/// Integrates the light scattered by `medium` towards the ray origin over `[t0, t1]` by marching
+/// along the ray, accounting for the media in front of each step.
+fn in_scattering(scene: &Scene,
+                 ray: &Ray,
+                 medium: &Medium,
+                 t0: f64,
+                 t1: f64)
+                 -> Result<Color, RenderError> {
+    let mut sum = BLACK;
+    if medium.scattering <= 0.0 || t1 <= t0 {
+        return Ok(sum);
+    }
+    let steps = scene.volume_steps.max(1);
+    let step = (t1 - t0) / steps as f64;
+    for i in 0..steps {
+        let t = t0 + (i as f64 + 0.5) * step;
+        let point = ray.origin + ray.direction * t;
+        let mut light_color = BLACK;
+        for (light, weight) in scene.lights_at(&point, None) {
+            light_color = light_color + light_arriving(scene, light, &point)? * weight;
+        }
+        let weight = medium.scattering * step as f32 * ISOTROPIC_PHASE *
+                     transmittance(scene, &ray.origin, &ray.direction, t);
+        sum = sum + light_color * weight;
+    }
+    Ok(sum * medium.color)
+}
+
+/// The light from `light` that reaches a point in free space.
+fn light_arriving(scene: &Scene, light: &Light, point: &Point) -> Result<Color, RenderError> {
+    let shadow_ray = Ray {
+        origin: *point,
+        direction: light.direction_from(point),
+        wavelength: None,
+    };
+    let transmission = shadow_transmission(scene, &shadow_ray, light.distance(point))?;
+    Ok(light.color() * light.intensity(point) * transmission)
+}
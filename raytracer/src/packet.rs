@@ -0,0 +1,221 @@
+use point::Point;
+use rendering::{Intersectable, Ray};
+use scene::{Element, Plane, Scene, Sphere, Triangle};
+use sdf::Sdf;
+use vector::Vector3;
+use std::f64;
+
+/// Number of rays traced together in a packet.
+pub const PACKET_SIZE: usize = 4;
+
+/// One value per ray of a packet.
+pub type Lanes = [f64; PACKET_SIZE];
+
+fn lanes<F: FnMut(usize) -> f64>(f: F) -> Lanes {
+    ::std::array::from_fn(f)
+}
+
+/// The coordinates of one point or vector per ray, stored as a structure of arrays so that the
+/// per-lane loops below compile to SIMD instructions.
+#[derive(Debug, Clone, Copy)]
+pub struct Vector3Packet {
+    pub x: Lanes,
+    pub y: Lanes,
+    pub z: Lanes,
+}
+impl Vector3Packet {
+    pub fn dot(&self, other: &Vector3Packet) -> Lanes {
+        lanes(|i| self.x[i] * other.x[i] + self.y[i] * other.y[i] + self.z[i] * other.z[i])
+    }
+
+    pub fn vector(&self, lane: usize) -> Vector3 {
+        Vector3 {
+            x: self.x[lane],
+            y: self.y[lane],
+            z: self.z[lane],
+        }
+    }
+
+    pub fn point(&self, lane: usize) -> Point {
+        Point {
+            x: self.x[lane],
+            y: self.y[lane],
+            z: self.z[lane],
+        }
+    }
+}
+
+/// Up to `PACKET_SIZE` rays traced together. The rays should be coherent, such as the primary
+/// rays of neighbouring pixels, so that they mostly visit the same acceleration structure nodes.
+#[derive(Debug, Clone)]
+pub struct RayPacket {
+    pub origin: Vector3Packet,
+    pub direction: Vector3Packet,
+    /// Lanes without a ray never hit anything.
+    pub active: [bool; PACKET_SIZE],
+    pub wavelength: Option<f32>,
+}
+impl RayPacket {
+    /// Packs the rays, with every lane active. The packet is traced at the wavelength of the
+    /// first ray.
+    pub fn new(rays: &[Ray; PACKET_SIZE]) -> RayPacket {
+        RayPacket {
+            origin: Vector3Packet {
+                x: lanes(|i| rays[i].origin.x),
+                y: lanes(|i| rays[i].origin.y),
+                z: lanes(|i| rays[i].origin.z),
+            },
+            direction: Vector3Packet {
+                x: lanes(|i| rays[i].direction.x),
+                y: lanes(|i| rays[i].direction.y),
+                z: lanes(|i| rays[i].direction.z),
+            },
+            active: [true; PACKET_SIZE],
+            wavelength: rays[0].wavelength,
+        }
+    }
+
+    /// The primary rays for the first `PACKET_SIZE` pixels. The lanes of pixels that the scene's
+    /// projection does not cover are inactive, as are the lanes past the last pixel.
+    pub fn create_prime(pixels: &[(u32, u32)], scene: &Scene) -> RayPacket {
+        let mut active = [false; PACKET_SIZE];
+        let rays: [Ray; PACKET_SIZE] = ::std::array::from_fn(|i| {
+            // Lanes past the last pixel repeat it to keep the packet coherent.
+            let pixel = pixels.get(i).or(pixels.last());
+            match pixel.and_then(|&(x, y)| Ray::create_prime(x, y, scene)) {
+                Some(ray) => {
+                    active[i] = i < pixels.len();
+                    ray
+                }
+                None => {
+                    Ray {
+                        origin: Point::zero(),
+                        direction: Vector3 {
+                            x: 0.0,
+                            y: 0.0,
+                            z: -1.0,
+                        },
+                        wavelength: None,
+                    }
+                }
+            }
+        });
+        RayPacket { active, ..RayPacket::new(&rays) }
+    }
+
+    pub fn ray(&self, lane: usize) -> Ray {
+        Ray {
+            origin: self.origin.point(lane),
+            direction: self.direction.vector(lane),
+            wavelength: self.wavelength,
+        }
+    }
+}
+
+pub trait PacketIntersectable {
+    /// The distance to the closest hit of each ray, or infinity for the rays that miss. Hits at
+    /// a distance that is not finite are reported as NaN so that they are not mistaken for misses.
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes;
+}
+
+fn hit(distance: f64) -> f64 {
+    if distance.is_finite() { distance } else { f64::NAN }
+}
+
+impl PacketIntersectable for Element {
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes {
+        match *self {
+            Element::Sphere(ref s) => s.intersect_packet(packet),
+            Element::Plane(ref p) => p.intersect_packet(packet),
+            Element::Triangle(ref t) => t.intersect_packet(packet),
+            Element::Sdf(ref s) => s.intersect_packet(packet),
+            // Instances are resolved by `Scene::trace_packet`, which needs the scene's groups.
+            Element::Instance(_) => [f64::INFINITY; PACKET_SIZE],
+        }
+    }
+}
+impl PacketIntersectable for Sphere {
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes {
+        let l = Vector3Packet {
+            x: lanes(|i| self.center.x - packet.origin.x[i]),
+            y: lanes(|i| self.center.y - packet.origin.y[i]),
+            z: lanes(|i| self.center.z - packet.origin.z[i]),
+        };
+        let adj = l.dot(&packet.direction);
+        let l2 = l.dot(&l);
+        let radius2 = self.radius * self.radius;
+        lanes(|i| {
+            let d2 = l2[i] - (adj[i] * adj[i]);
+            if !packet.active[i] || d2 > radius2 {
+                return f64::INFINITY;
+            }
+            let thc = (radius2 - d2).sqrt();
+            let t0 = adj[i] - thc;
+            let t1 = adj[i] + thc;
+            if t0 < 0.0 && t1 < 0.0 {
+                f64::INFINITY
+            } else if t0 < 0.0 {
+                hit(t1)
+            } else if t1 < 0.0 || t0 < t1 {
+                hit(t0)
+            } else {
+                hit(t1)
+            }
+        })
+    }
+}
+impl PacketIntersectable for Plane {
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes {
+        let normal = &self.normal;
+        lanes(|i| {
+            let denom = normal.x * packet.direction.x[i] + normal.y * packet.direction.y[i] +
+                        normal.z * packet.direction.z[i];
+            let v = Vector3 {
+                x: self.origin.x - packet.origin.x[i],
+                y: self.origin.y - packet.origin.y[i],
+                z: self.origin.z - packet.origin.z[i],
+            };
+            let distance = v.dot(normal) / denom;
+            if packet.active[i] && denom > 1e-6 && distance >= 0.0 {
+                hit(distance)
+            } else {
+                f64::INFINITY
+            }
+        })
+    }
+}
+impl PacketIntersectable for Triangle {
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes {
+        let [a, b, c] = self.vertices;
+        let (e1, e2) = (b - a, c - a);
+        lanes(|i| {
+            let direction = packet.direction.vector(i);
+            let p = direction.cross(&e2);
+            let det = e1.dot(&p);
+            if !packet.active[i] || det.abs() < 1e-12 {
+                return f64::INFINITY;
+            }
+            let inv_det = 1.0 / det;
+            let t = packet.origin.point(i) - a;
+            let u = t.dot(&p) * inv_det;
+            let q = t.cross(&e1);
+            let v = direction.dot(&q) * inv_det;
+            let distance = e2.dot(&q) * inv_det;
+            if (0.0..=1.0).contains(&u) && v >= 0.0 && u + v <= 1.0 && distance > 0.0 {
+                hit(distance)
+            } else {
+                f64::INFINITY
+            }
+        })
+    }
+}
+impl PacketIntersectable for Sdf {
+    /// Each ray takes a different number of steps, so they are marched one at a time.
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes {
+        lanes(|i| if packet.active[i] {
+            self.intersect(&packet.ray(i)).map_or(f64::INFINITY, hit)
+        } else {
+            f64::INFINITY
+        })
+    }
+}
@@ -0,0 +1,496 @@
+use bvh::{Bounded, BoundingBox};
+use error::RenderError;
+use point::Point;
+use rendering::{BLACK, Ray, conductor_fresnel, face_forward, fresnel};
+use scene::{Color, Element, Light, Scene, SurfaceType};
+use vector::Vector3;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64;
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+fn default_neighbours() -> usize {
+    64
+}
+
+/// Enables caustics. Before the first pixel is shaded, photons are shot from the lights at the
+/// reflective and refractive elements, and the ones that land on a diffuse surface after
+/// bouncing off or passing through them are stored. Shading a diffuse surface then adds the
+/// light of the photons around the shaded point.
+///
+/// The light that bounded refractive elements let through is carried by the photons, so by
+/// default those elements block shadow rays entirely instead of tinting them; see
+/// `transmitted_shadows`. Photons ignore fog, volumes and dispersion, and unbounded elements such
+/// as planes do not focus light.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CausticSettings {
+    /// Number of photons shot by each light.
+    pub photons: u32,
+    /// Largest distance from a shaded point at which photons are gathered.
+    pub radius: f64,
+    /// Number of nearest photons the estimate is made from. Fewer photons give sharper but
+    /// noisier caustics.
+    #[serde(default = "default_neighbours")]
+    pub neighbours: usize,
+    /// Lets shadow rays pass through bounded refractive elements, tinted as without caustics.
+    /// The light they let through is then counted twice, once in the shadow and once in the
+    /// photons, but shadows stay lit where there are too few photons to cover them.
+    #[serde(default)]
+    pub transmitted_shadows: bool,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    map: OnceLock<PhotonMap>,
+}
+impl CausticSettings {
+    pub fn new(photons: u32, radius: f64) -> CausticSettings {
+        CausticSettings {
+            photons,
+            radius,
+            neighbours: default_neighbours(),
+            transmitted_shadows: false,
+            map: OnceLock::new(),
+        }
+    }
+
+    /// The photon map of the scene, built the first time it is needed. The render functions
+    /// build it before the first tile, so that it is not counted in the tile's time.
+    pub fn photon_map(&self, scene: &Scene) -> Result<&PhotonMap, RenderError> {
+        if let Some(map) = self.map.get() {
+            return Ok(map);
+        }
+        let map = PhotonMap::build(scene, self.photons)?;
+        Ok(self.map.get_or_init(|| map))
+    }
+
+    /// The irradiance caused by caustics at a point of a surface facing `normal`.
+    pub fn irradiance(&self,
+                      scene: &Scene,
+                      point: &Point,
+                      normal: &Vector3)
+                      -> Result<Color, RenderError> {
+        let map = self.photon_map(scene)?;
+        Ok(map.irradiance(point, normal, self.neighbours, self.radius))
+    }
+}
+
+/// A unit of light power left on a surface, along with the direction it was travelling in.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub position: Point,
+    pub direction: Vector3,
+    pub power: Color,
+}
+
+/// Photons stored as a balanced kd-tree: the root of every range of `photons` is its middle
+/// element, split along the axis stored at the same index of `axes`.
+#[derive(Debug)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        balance(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    /// Shoots `photons_per_light` photons from every light of the scene and keeps those that
+    /// reached a diffuse surface through at least one specular bounce.
+    pub fn build(scene: &Scene, photons_per_light: u32) -> Result<PhotonMap, RenderError> {
+        let targets = specular_targets(scene);
+        let mut photons = vec![];
+        if targets.is_empty() {
+            return Ok(PhotonMap::new(photons));
+        }
+        let per_target = (photons_per_light as usize / targets.len()).max(1);
+        let scene_bounds = scene_bounds(scene);
+        let mut seed = 0;
+        for light in &scene.lights {
+            for target in &targets {
+                for i in 0..per_target {
+                    let sample = (i as f64 + 0.5) / per_target as f64;
+                    let (ray, power) = emit(light,
+                                            target,
+                                            &scene_bounds,
+                                            (sample, radical_inverse(i as u32)),
+                                            per_target);
+                    // Directions aimed at several targets would otherwise be sampled once for
+                    // each of them.
+                    let overlap = targets.iter().filter(|t| t.is_hit(&ray)).count().max(1);
+                    let power = power * (1.0 / overlap as f32);
+                    trace_photon(scene, ray, power, seed, &mut photons)?;
+                    seed += 1;
+                }
+            }
+        }
+        Ok(PhotonMap::new(photons))
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Estimates the irradiance at a point from the power of the `neighbours` photons closest to
+    /// it, within `radius`, that arrived on the side of the surface `normal` points to.
+    pub fn irradiance(&self,
+                      point: &Point,
+                      normal: &Vector3,
+                      neighbours: usize,
+                      radius: f64)
+                      -> Color {
+        let mut nearest = BinaryHeap::new();
+        let mut max_distance2 = radius * radius;
+        self.nearest(0, self.photons.len(), point, neighbours, &mut max_distance2, &mut nearest);
+        if nearest.is_empty() {
+            return BLACK;
+        }
+        let power = nearest.iter()
+            .map(|n: &Neighbour| &self.photons[n.index])
+            .filter(|p| p.direction.dot(normal) < 0.0)
+            .fold(BLACK, |sum, p| sum + p.power);
+        // The photons are spread over the disc that reaches the furthest one kept.
+        let area = PI * max_distance2;
+        power * (1.0 / area as f32)
+    }
+
+    /// Collects the `count` photons closest to the point within the subtree of `start..end`,
+    /// shrinking `max_distance2` to the furthest of them once enough were found.
+    fn nearest(&self,
+               start: usize,
+               end: usize,
+               point: &Point,
+               count: usize,
+               max_distance2: &mut f64,
+               nearest: &mut BinaryHeap<Neighbour>) {
+        if start >= end || count == 0 {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        let axis = self.axes[middle];
+        let delta = coordinate(point, axis) - coordinate(&photon.position, axis);
+        let (near, far) = if delta < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+
+        self.nearest(near.0, near.1, point, count, max_distance2, nearest);
+        let distance2 = (photon.position - *point).norm();
+        if distance2 < *max_distance2 {
+            nearest.push(Neighbour {
+                distance2,
+                index: middle,
+            });
+            if nearest.len() > count {
+                nearest.pop();
+            }
+            if nearest.len() == count {
+                *max_distance2 = nearest.peek().map_or(*max_distance2, |n| n.distance2);
+            }
+        }
+        if delta * delta < *max_distance2 {
+            self.nearest(far.0, far.1, point, count, max_distance2, nearest);
+        }
+    }
+}
+
+/// A photon found by a nearest-neighbour search, ordered by distance so that the heap of
+/// neighbours keeps the furthest one on top.
+struct Neighbour {
+    distance2: f64,
+    index: usize,
+}
+impl PartialEq for Neighbour {
+    fn eq(&self, other: &Neighbour) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Neighbour {}
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Neighbour) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Neighbour) -> Ordering {
+        self.distance2.total_cmp(&other.distance2)
+    }
+}
+
+fn coordinate(point: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+/// Arranges the photons into a kd-tree, splitting every range at its median along the axis on
+/// which its photons are spread the most.
+fn balance(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+        return;
+    }
+    let bounds = photons.iter().fold(BoundingBox::empty(), |b, p| b.include(&p.position));
+    let extent = bounds.max - bounds.min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        coordinate(&a.position, axis).total_cmp(&coordinate(&b.position, axis))
+    });
+    axes[middle] = axis;
+    let (left, right) = photons.split_at_mut(middle);
+    let (left_axes, right_axes) = axes.split_at_mut(middle);
+    balance(left, left_axes);
+    balance(&mut right[1..], &mut right_axes[1..]);
+}
+
+/// The bounding sphere of an element that can focus light, which photons are aimed at.
+struct Target {
+    center: Point,
+    radius: f64,
+}
+impl Target {
+    fn from_bounds(bounds: &BoundingBox) -> Target {
+        Target {
+            center: bounds.centroid(),
+            radius: (bounds.max - bounds.min).length() * 0.5,
+        }
+    }
+
+    fn is_hit(&self, ray: &Ray) -> bool {
+        let to_center = self.center - ray.origin;
+        let along = to_center.dot(&ray.direction);
+        let radius2 = self.radius * self.radius;
+        let distance2 = to_center.norm() - along * along;
+        distance2 <= radius2 && (along >= 0.0 || to_center.norm() <= radius2)
+    }
+}
+
+fn is_specular(element: &Element) -> bool {
+    element.material().is_some_and(|m| {
+        matches!(m.surface,
+                 SurfaceType::Reflective { .. } | SurfaceType::Metal(_) |
+                 SurfaceType::Refractive { .. })
+    })
+}
+
+/// The bounds of every element, or instance, that reflects or refracts light.
+fn specular_targets(scene: &Scene) -> Vec<Target> {
+    scene.elements
+        .iter()
+        .filter_map(|element| match *element {
+            Element::Instance(ref instance) => {
+                let group = scene.groups.get(&instance.group)?;
+                if group.elements.iter().any(is_specular) {
+                    group.bounds().map(|b| instance.transform.bounds_to_world(&b))
+                } else {
+                    None
+                }
+            }
+            _ if is_specular(element) => element.bounding_box(),
+            _ => None,
+        })
+        .map(|bounds| Target::from_bounds(&bounds))
+        .collect()
+}
+
+/// The bounding sphere of all the bounded elements, outside of which directional photons start.
+fn scene_bounds(scene: &Scene) -> Target {
+    let bounds = scene.elements
+        .iter()
+        .filter_map(|element| match *element {
+            Element::Instance(ref instance) => {
+                scene.groups
+                    .get(&instance.group)
+                    .and_then(|g| g.bounds())
+                    .map(|b| instance.transform.bounds_to_world(&b))
+            }
+            _ => element.bounding_box(),
+        })
+        .fold(BoundingBox::empty(), |a, b| a.union(&b));
+    Target::from_bounds(&bounds)
+}
+
+/// An orthonormal basis whose third axis is `w`.
+fn basis(w: Vector3) -> (Vector3, Vector3) {
+    let other = if w.x.abs() > 0.9 {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    } else {
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    };
+    let u = w.cross(&other).normalize();
+    (u, w.cross(&u))
+}
+
+/// The `sample`th of `count` photons that the light shoots at the target, with its power. Point
+/// lights shoot into the cone that the target covers, and directional lights shoot through the
+/// disc it covers, both uniformly.
+fn emit(light: &Light,
+        target: &Target,
+        scene_bounds: &Target,
+        sample: (f64, f64),
+        count: usize)
+        -> (Ray, Color) {
+    let (u, v) = sample;
+    let phi = 2.0 * PI * v;
+    let (position, intensity, spot) = match *light {
+        Light::Directional(ref d) => {
+            let (x, y) = basis(d.direction);
+            let offset = (x * phi.cos() + y * phi.sin()) * (target.radius * u.sqrt());
+            // Start outside the scene so that anything between the light and the target casts a
+            // shadow, like it does for shadow rays.
+            let distance = (target.center - scene_bounds.center).length() + scene_bounds.radius +
+                           target.radius;
+            let ray = Ray {
+                origin: target.center + offset - d.direction * distance,
+                direction: d.direction,
+                wavelength: None,
+            };
+            let area = PI * target.radius * target.radius;
+            let power = d.color * (d.intensity * (area / count as f64) as f32);
+            return (ray, power);
+        }
+        Light::Spherical(ref s) => (s.position, s.intensity, None),
+        Light::Spot(ref s) => (s.position, s.intensity, Some(s)),
+    };
+
+    let to_target = target.center - position;
+    let distance = to_target.length();
+    // A light inside the target's bounds shoots in every direction.
+    let cos_max = if distance > target.radius {
+        (1.0 - (target.radius / distance).powi(2)).sqrt()
+    } else {
+        -1.0
+    };
+    let axis = if distance > 0.0 {
+        to_target * (1.0 / distance)
+    } else {
+        Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        }
+    };
+    let (x, y) = basis(axis);
+    let cos_theta = 1.0 - u * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let direction = (x * (phi.cos() * sin_theta) + y * (phi.sin() * sin_theta) +
+                     axis * cos_theta)
+        .normalize();
+
+    // Point lights spread their intensity evenly over the sphere of directions.
+    let solid_angle = 2.0 * PI * (1.0 - cos_max);
+    let mut power = light.color() * (intensity * (solid_angle / (4.0 * PI * count as f64)) as f32);
+    if let Some(spot) = spot {
+        power = power * spot.falloff(&(position + direction));
+    }
+    let ray = Ray {
+        origin: position,
+        direction,
+        wavelength: None,
+    };
+    (ray, power)
+}
+
+/// Follows a photon through specular bounces until it reaches a diffuse surface. Refractive
+/// surfaces either reflect or transmit the photon, with the Fresnel reflectance as the
+/// probability of reflecting it, so that its power does not need to be split.
+fn trace_photon(scene: &Scene,
+                mut ray: Ray,
+                mut power: Color,
+                seed: u64,
+                photons: &mut Vec<Photon>)
+                -> Result<(), RenderError> {
+    let bias = scene.shadow_bias;
+    for bounce in 0..scene.max_recursion_depth {
+        let intersection = match scene.trace(&ray)? {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let hit = ray.origin + (ray.direction * intersection.distance);
+        let normal = intersection.surface_normal(&hit);
+        let facing = face_forward(normal, ray.direction);
+        let material = intersection.material;
+        let store = bounce > 0;
+
+        ray = match material.surface {
+            SurfaceType::Diffuse => {
+                if store {
+                    photons.push(Photon {
+                        position: hit,
+                        direction: ray.direction,
+                        power,
+                    });
+                }
+                return Ok(());
+            }
+            SurfaceType::Reflective { reflectivity } => {
+                if store {
+                    photons.push(Photon {
+                        position: hit,
+                        direction: ray.direction,
+                        power,
+                    });
+                }
+                power = power * reflectivity;
+                Ray::create_reflection(facing, ray.direction, hit, bias)
+            }
+            SurfaceType::Metal(ref metal) => {
+                let (n, k) = metal.index();
+                power = power * conductor_fresnel(ray.direction, facing, n, k);
+                Ray::create_reflection(facing, ray.direction, hit, bias)
+            }
+            SurfaceType::Refractive { index, transparency, .. } => {
+                let surface_color = material.coloration
+                    .color(&intersection.texture_coords(&hit));
+                power = power * surface_color * transparency;
+                let kr = fresnel(ray.direction, normal, index);
+                let transmission = if random(seed, bounce) >= kr {
+                    Ray::create_transmission(normal, ray.direction, hit, bias, index)
+                } else {
+                    None
+                };
+                transmission.unwrap_or_else(|| {
+                    Ray::create_reflection(normal, ray.direction, hit, bias)
+                })
+            }
+        };
+    }
+    Ok(())
+}
+
+/// The base-2 radical inverse of `i`, which spreads consecutive integers evenly over [0, 1).
+fn radical_inverse(i: u32) -> f64 {
+    i.reverse_bits() as f64 / (1u64 << 32) as f64
+}
+
+/// A number in [0, 1) that looks random but is the same for every render of the scene.
+pub fn random(seed: u64, bounce: u32) -> f64 {
+    let mut z = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (bounce as u64).wrapping_add(1);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -9,6 +9,10 @@ pub struct Point {
     pub z: f64,
 }
 impl Point {
+    pub fn new(x: f64, y: f64, z: f64) -> Point {
+        Point { x, y, z }
+    }
+
     pub fn zero() -> Point {
         Point::from_one(0.0)
     }
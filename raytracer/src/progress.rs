@@ -0,0 +1,51 @@
+use stats::TileStats;
+use std::time::Duration;
+use ViewBlock;
+
+/// Snapshot of a tiled render, handed to the progress callback after each tile completes.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub completed_tiles: u32,
+    /// Tiles that were restored from a checkpoint rather than rendered in this session.
+    pub resumed_tiles: u32,
+    pub total_tiles: u32,
+    pub elapsed: Duration,
+
+    /// The tile that was just finished, in image coordinates.
+    pub tile: ViewBlock,
+    /// The time taken and the rays traced to render the tile that was just finished.
+    pub stats: TileStats,
+}
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.total_tiles == 0 {
+            1.0
+        } else {
+            self.completed_tiles as f64 / self.total_tiles as f64
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.completed_tiles >= self.total_tiles
+    }
+
+    /// Estimated time remaining, extrapolated from the average time per tile rendered so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let rendered = self.completed_tiles - self.resumed_tiles;
+        if rendered == 0 {
+            return None;
+        }
+        let elapsed = duration_secs(self.elapsed);
+        let remaining = (self.total_tiles - self.completed_tiles) as f64;
+        let per_tile = elapsed / rendered as f64;
+        Some(secs_duration(per_tile * remaining))
+    }
+}
+
+pub fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+fn secs_duration(secs: f64) -> Duration {
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
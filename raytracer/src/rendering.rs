@@ -1,32 +1,67 @@
 use point::Point;
 use vector::Vector3;
-use scene::{Scene, Element, Sphere, Plane, Color, Intersection, SurfaceType};
+use scene::{Scene, Element, Sphere, Plane, Triangle, Color, Intersection, SurfaceType};
+use error::RenderError;
 use std::f32;
+use std::ops::{Add, Mul};
+use media;
+use packet::{PACKET_SIZE, RayPacket};
+use bvh::Bounded;
+use stats::{self, RayKind};
 
 #[derive(Debug)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    /// The wavelength carried by the ray in spectral mode, in nanometers.
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
-    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
-        assert!(scene.width >= scene.height);
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x = ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) *
-                       fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Ray {
+        Ray { wavelength, ..self }
+    }
 
-        Ray {
-            origin: Point::zero(),
-            direction: Vector3 {
-                    x: sensor_x,
-                    y: sensor_y,
-                    z: -1.0,
+    /// The ray from the camera through the center of a pixel, or `None` if the scene's projection
+    /// does not cover the pixel. The scene must pass `Scene::validate`.
+    ///
+    /// In stereo, each half of the image is projected on its own, from its own eye.
+    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Option<Ray> {
+        Ray::create_prime_sample(x, y, (0.5, 0.5), scene)
+    }
+
+    /// Like `create_prime`, through a point of the pixel given by its offset from the top-left
+    /// corner of the pixel, from 0 to 1 on each axis.
+    pub fn create_prime_sample(x: u32,
+                               y: u32,
+                               offset: (f64, f64),
+                               scene: &Scene)
+                               -> Option<Ray> {
+        let (x, width, eye) = match scene.stereo {
+            Some(ref stereo) => {
+                let half = scene.width / 2;
+                if x < half {
+                    (x, half, Some((stereo, true)))
+                } else {
+                    (x - half, scene.width - half, Some((stereo, false)))
                 }
-                .normalize(),
+            }
+            None => (x, scene.width, None),
+        };
+        let aspect_ratio = (width as f64) / (scene.height as f64);
+        let sensor_x = (((x as f64 + offset.0) / width as f64) * 2.0 - 1.0) * aspect_ratio;
+        let sensor_y = 1.0 - ((y as f64 + offset.1) / scene.height as f64) * 2.0;
+
+        let (mut origin, direction) =
+            scene.projection.ray(sensor_x, sensor_y, aspect_ratio, scene.fov)?;
+        if let Some((stereo, left)) = eye {
+            origin = origin + stereo.eye_offset(&scene.projection, &direction, left);
         }
+        Some(Ray {
+            origin,
+            direction,
+            wavelength: None,
+        })
     }
 
     pub fn create_reflection(normal: Vector3,
@@ -37,6 +72,21 @@ impl Ray {
         Ray {
             origin: intersection + (normal * bias),
             direction: incident - (2.0 * incident.dot(&normal) * normal),
+            wavelength: None,
+        }
+    }
+
+    /// A ray carrying on in the same direction from just past a surface that it passes through.
+    pub fn create_continuation(normal: Vector3,
+                               direction: Vector3,
+                               intersection: Point,
+                               bias: f64)
+                               -> Ray {
+        let offset = if direction.dot(&normal) < 0.0 { -bias } else { bias };
+        Ray {
+            origin: intersection + (normal * offset),
+            direction,
+            wavelength: None,
         }
     }
 
@@ -68,6 +118,7 @@ impl Ray {
             Some(Ray {
                 origin: intersection + (ref_n * -bias),
                 direction: (incident + i_dot_n * ref_n) * eta - ref_n * k.sqrt(),
+                wavelength: None,
             })
         }
     }
@@ -91,6 +142,10 @@ impl Intersectable for Element {
         match *self {
             Element::Sphere(ref s) => s.intersect(ray),
             Element::Plane(ref p) => p.intersect(ray),
+            Element::Triangle(ref t) => t.intersect(ray),
+            Element::Sdf(ref s) => s.intersect(ray),
+            // Instances are resolved by `Scene::trace`, which needs the scene's groups.
+            Element::Instance(_) => None,
         }
     }
 
@@ -98,6 +153,10 @@ impl Intersectable for Element {
         match *self {
             Element::Sphere(ref s) => s.surface_normal(hit_point),
             Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Triangle(ref t) => t.surface_normal(hit_point),
+            Element::Sdf(ref s) => s.surface_normal(hit_point),
+            // Instances are never hit directly, so they have no surface of their own.
+            Element::Instance(_) => Vector3::zero(),
         }
     }
 
@@ -105,6 +164,9 @@ impl Intersectable for Element {
         match *self {
             Element::Sphere(ref s) => s.texture_coords(hit_point),
             Element::Plane(ref p) => p.texture_coords(hit_point),
+            Element::Triangle(ref t) => t.texture_coords(hit_point),
+            Element::Sdf(ref s) => s.texture_coords(hit_point),
+            Element::Instance(_) => TextureCoords { x: 0.0, y: 0.0 },
         }
     }
 }
@@ -186,85 +248,312 @@ impl Intersectable for Plane {
     }
 }
 
-const BLACK: Color = Color {
+impl Intersectable for Triangle {
+    /// Möller-Trumbore intersection; both faces can be hit.
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let [a, b, c] = self.vertices;
+        let (e1, e2) = (b - a, c - a);
+        let p = ray.direction.cross(&e2);
+        let det = e1.dot(&p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let t = ray.origin - a;
+        let u = t.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = t.cross(&e1);
+        let v = ray.direction.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = e2.dot(&q) * inv_det;
+        if distance > 0.0 { Some(distance) } else { None }
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        match self.normals {
+            Some(normals) => {
+                let weights = self.barycentric(hit_point);
+                (normals[0] * weights[0] + normals[1] * weights[1] + normals[2] * weights[2])
+                    .normalize()
+            }
+            None => self.geometric_normal(),
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let weights = self.barycentric(hit_point);
+        match self.tex_coords {
+            Some(uv) => {
+                let lerp = |i: usize| {
+                    (uv[0][i] as f64 * weights[0] + uv[1][i] as f64 * weights[1] +
+                     uv[2][i] as f64 * weights[2]) as f32
+                };
+                TextureCoords {
+                    x: lerp(0),
+                    y: lerp(1),
+                }
+            }
+            None => {
+                TextureCoords {
+                    x: weights[1] as f32,
+                    y: weights[2] as f32,
+                }
+            }
+        }
+    }
+}
+
+pub const BLACK: Color = Color {
     red: 0.0,
     green: 0.0,
     blue: 0.0,
 };
 
+/// The fraction of light, per channel, that makes it along the shadow ray over `distance`.
+///
+/// Opaque elements block the light completely. Refractive elements let some of it through,
+/// tinted by their color and scaled by their transparency and by the Fresnel transmittance where
+/// the ray enters them, so that glass casts colored, partial shadows. Shadow rays are not bent by
+/// refraction. Elements that are not fully opaque let the rest of the light through untinted,
+/// and their cut out parts let all of it through. Media along the way attenuate the light as
+/// well.
+pub fn shadow_transmission(scene: &Scene,
+                           shadow_ray: &Ray,
+                           distance: f64)
+                           -> Result<Color, RenderError> {
+    stats::count_ray(RayKind::Shadow);
+    let mut transmission = Color {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    };
+    let mut ray = Ray {
+        origin: shadow_ray.origin,
+        direction: shadow_ray.direction,
+        wavelength: shadow_ray.wavelength,
+    };
+    let mut remaining = distance;
+    for _ in 0..scene.max_recursion_depth.max(1) {
+        let intersection = match scene.trace(&ray)? {
+            Some(i) if i.distance < remaining => i,
+            _ => {
+                let media = media::transmittance(scene, &ray.origin, &ray.direction, remaining);
+                return Ok(transmission * media);
+            }
+        };
+        let media =
+            media::transmittance(scene, &ray.origin, &ray.direction, intersection.distance);
+        transmission = transmission * media;
+
+        let element = intersection.element;
+        let hit = ray.origin + (ray.direction * intersection.distance);
+        let normal = intersection.surface_normal(&hit);
+        let entering = ray.direction.dot(&normal) < 0.0;
+        // With caustics, the light passing through bounded refractive elements is carried by
+        // photons, unless the settings let shadow rays through as well.
+        let photon_carried = || {
+            scene.caustics.as_ref().is_some_and(|c| !c.transmitted_shadows) &&
+            element.bounding_box().is_some()
+        };
+        let refracted = match intersection.material.surface {
+            SurfaceType::Refractive { .. } if photon_carried() => None,
+            SurfaceType::Refractive { index, transparency, .. } => {
+                let surface_color =
+                    intersection.material.coloration.color(&intersection.texture_coords(&hit));
+                let mut factor = surface_color * transparency;
+                if entering {
+                    factor = factor * (1.0 - fresnel(ray.direction, normal, index) as f32);
+                }
+                Some(factor)
+            }
+            _ => None,
+        };
+        let coverage = intersection.coverage(&hit);
+        let factor = match refracted {
+            Some(factor) => factor * coverage + Color::gray(1.0 - coverage),
+            None if coverage < 1.0 => Color::gray(1.0 - coverage),
+            None => return Ok(BLACK),
+        };
+        transmission = transmission * factor;
+
+        // Continue from just past the surface, on the side the ray is travelling to.
+        remaining -= intersection.distance;
+        stats::count_ray(RayKind::Continuation);
+        ray = Ray::create_continuation(normal, ray.direction, hit, scene.shadow_bias)
+            .with_wavelength(ray.wavelength);
+    }
+    Ok(BLACK)
+}
+
+const SURFACE_MARGIN: f64 = 1.0 - 1e-9;
+
 fn shade_diffuse(scene: &Scene,
-                 element: &Element,
+                 intersection: &Intersection,
                  hit_point: Point,
                  surface_normal: Vector3)
-                 -> Color {
-    let texture_coords = element.texture_coords(&hit_point);
+                 -> Result<Color, RenderError> {
+    let texture_coords = intersection.texture_coords(&hit_point);
     let mut color = BLACK;
-    for light in &scene.lights {
+    for (light, weight) in scene.lights_at(&hit_point, Some(&surface_normal)) {
         let direction_to_light = light.direction_from(&hit_point);
 
         let shadow_ray = Ray {
             origin: hit_point + (surface_normal * scene.shadow_bias),
             direction: direction_to_light,
+            wavelength: None,
         };
-        let shadow_intersection = scene.trace(&shadow_ray);
-        let in_light = shadow_intersection.is_none() ||
-                       shadow_intersection.unwrap().distance > light.distance(&hit_point);
-
-        let light_intensity = if in_light {
-            light.intensity(&hit_point)
-        } else {
-            0.0
-        };
-        let material = element.material();
+        let transmission = shadow_transmission(scene, &shadow_ray, light.distance(&hit_point))?;
+        let light_intensity = light.intensity(&hit_point);
+        let material = intersection.material;
         let light_power = (surface_normal.dot(&direction_to_light) as f32).max(0.0) *
                           light_intensity;
         let light_reflected = material.albedo / f32::consts::PI;
 
-        let light_color = light.color() * light_power * light_reflected;
+        let light_color = light.color() * transmission * light_power * light_reflected * weight;
+        color = color + (material.coloration.color(&texture_coords) * light_color);
+    }
+    for emitter in scene.emitters() {
+        if !emitter.instanced && emitter.index == intersection.index {
+            continue;
+        }
+        let view = match emitter.view(&hit_point) {
+            Some(view) => view,
+            None => continue,
+        };
+        let cosine = (surface_normal.dot(&view.direction) as f32).max(0.0);
+        if cosine <= 0.0 {
+            continue;
+        }
+        let shadow_ray = Ray {
+            origin: hit_point + (surface_normal * scene.shadow_bias),
+            direction: view.direction,
+            wavelength: None,
+        };
+        // Stop just short of the emitter's surface so that it does not shadow itself.
+        let shadow_distance = view.distance * SURFACE_MARGIN;
+        let transmission = shadow_transmission(scene, &shadow_ray, shadow_distance)?;
+        let material = intersection.material;
+        let light_power = cosine * view.solid_angle as f32;
+        let light_reflected = material.albedo / f32::consts::PI;
+
+        let light_color = emitter.radiance * transmission * light_power * light_reflected;
         color = color + (material.coloration.color(&texture_coords) * light_color);
     }
-    color.clamp()
+    if let Some(ref caustics) = scene.caustics {
+        let irradiance = caustics.irradiance(scene, &hit_point, &surface_normal)?;
+        let material = intersection.material;
+        let light_reflected = material.albedo / f32::consts::PI;
+        color = color + (material.coloration.color(&texture_coords) * irradiance * light_reflected);
+    }
+    // Sampled lights are scaled up to make up for the lights left out, so a single estimate may
+    // be brighter than white; clamping it would darken the average.
+    if scene.light_sampling.is_some() {
+        Ok(color)
+    } else {
+        Ok(color.clamp())
+    }
+}
+
+/// The light that reaches the hit point straight from the light sources, plus the light the
+/// surface emits itself.
+pub fn direct_color(scene: &Scene,
+                    ray: &Ray,
+                    intersection: &Intersection)
+                    -> Result<Color, RenderError> {
+    let hit = ray.origin + (ray.direction * intersection.distance);
+    let normal = face_forward(intersection.surface_normal(&hit), ray.direction);
+
+    let material = intersection.material;
+    let reflected = match material.surface {
+        SurfaceType::Diffuse => shade_diffuse(scene, intersection, hit, normal)?,
+        SurfaceType::Reflective { reflectivity } => {
+            shade_diffuse(scene, intersection, hit, normal)? * (1.0 - reflectivity)
+        }
+        SurfaceType::Metal(_) |
+        SurfaceType::Refractive { .. } => BLACK,
+    };
+    Ok(reflected + material.emitted())
 }
 
-fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
+/// The light that reaches the hit point through reflected and refracted rays.
+pub fn indirect_color(scene: &Scene,
+                      ray: &Ray,
+                      intersection: &Intersection,
+                      depth: u32)
+                      -> Result<Color, RenderError> {
     let hit = ray.origin + (ray.direction * intersection.distance);
-    let normal = intersection.element.surface_normal(&hit);
+    let normal = intersection.surface_normal(&hit);
+    let facing = face_forward(normal, ray.direction);
 
-    let material = intersection.element.material();
+    let material = intersection.material;
     match material.surface {
-        SurfaceType::Diffuse => shade_diffuse(scene, intersection.element, hit, normal),
+        SurfaceType::Diffuse => Ok(BLACK),
         SurfaceType::Reflective { reflectivity } => {
-            let mut color = shade_diffuse(scene, intersection.element, hit, normal);
             let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
-            color = color * (1.0 - reflectivity);
-            color = color + (cast_ray(scene, &reflection_ray, depth + 1) * reflectivity);
-            color
+                Ray::create_reflection(facing, ray.direction, hit, scene.shadow_bias)
+                    .with_wavelength(ray.wavelength);
+            stats::count_ray(RayKind::Reflection);
+            Ok(cast_ray(scene, &reflection_ray, depth + 1)? * reflectivity)
+        }
+        SurfaceType::Metal(ref metal) => {
+            let (n, k) = metal.index();
+            let reflection_ray =
+                Ray::create_reflection(facing, ray.direction, hit, scene.shadow_bias)
+                    .with_wavelength(ray.wavelength);
+            stats::count_ray(RayKind::Reflection);
+            Ok(cast_ray(scene, &reflection_ray, depth + 1)? *
+               conductor_fresnel(ray.direction, facing, n, k))
         }
-        SurfaceType::Refractive { index, transparency } => {
+        SurfaceType::Refractive { index, transparency, ref dispersion } => {
+            let index = match (dispersion.as_ref(), ray.wavelength) {
+                (Some(dispersion), Some(wavelength)) => dispersion.index(wavelength),
+                _ => index,
+            };
             let mut refraction_color = BLACK;
-            let kr = fresnel(ray.direction, normal, index) as f32;
+            let mut kr = fresnel(ray.direction, normal, index) as f32;
             let surface_color = material.coloration
-                .color(&intersection.element.texture_coords(&hit));
+                .color(&intersection.texture_coords(&hit));
 
             if kr < 1.0 {
-                let transmission_ray =
-                    Ray::create_transmission(normal, ray.direction, hit, scene.shadow_bias, index)
-                        .unwrap();
-                refraction_color = cast_ray(scene, &transmission_ray, depth + 1);
+                let bias = scene.shadow_bias;
+                match Ray::create_transmission(normal, ray.direction, hit, bias, index) {
+                    Some(transmission_ray) => {
+                        let transmission_ray = transmission_ray.with_wavelength(ray.wavelength);
+                        stats::count_ray(RayKind::Refraction);
+                        refraction_color = cast_ray(scene, &transmission_ray, depth + 1)?;
+                    }
+                    // Rounding at grazing angles can make the Fresnel term and the refracted
+                    // direction disagree; treat it as total internal reflection.
+                    None => kr = 1.0,
+                }
             }
 
             let reflection_ray =
-                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias);
-            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
+                Ray::create_reflection(normal, ray.direction, hit, scene.shadow_bias)
+                    .with_wavelength(ray.wavelength);
+            stats::count_ray(RayKind::Reflection);
+            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1)?;
             let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
             color = color * transparency * surface_color;
-            color
+            Ok(color)
         }
     }
 }
 
-fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
+/// Flips the normal if needed so that it faces against `direction`. Triangles can be hit from
+/// either side, so surfaces that only reflect light are shaded with this normal, while refraction
+/// keeps the outward normal to tell rays entering an element from rays leaving it.
+pub fn face_forward(normal: Vector3, direction: Vector3) -> Vector3 {
+    if normal.dot(&direction) > 0.0 { -normal } else { normal }
+}
+
+/// The fraction of the light reflected where a ray meets the surface of a material with the
+/// given index of refraction, from either side.
+pub fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     let i_dot_n = incident.dot(&normal);
     let mut eta_i = 1.0;
     let mut eta_t = index as f64;
@@ -286,12 +575,208 @@ fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     }
 }
 
-pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Color {
+/// The fraction of the light reflected by a metal with the complex index of refraction `n + ik`
+/// in each color channel, seen from outside.
+pub fn conductor_fresnel(incident: Vector3, normal: Vector3, n: Color, k: Color) -> Color {
+    let cos_i = incident.dot(&normal).abs().min(1.0) as f32;
+    let reflectance = |n: f32, k: f32| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1.0 - cos2;
+        let t0 = n * n - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * n * n * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * a * cos_i;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        (r_s + r_p) / 2.0
+    };
+    Color {
+        red: reflectance(n.red, k.red),
+        green: reflectance(n.green, k.green),
+        blue: reflectance(n.blue, k.blue),
+    }
+}
+
+/// The light seen along a ray, split by how it reached the first surface hit: the direct part
+/// came straight from the light sources or was emitted there, and the indirect part came through
+/// reflected and refracted rays. The light seen through the parts of surfaces that let it through
+/// is split the same way, and the light scattered by fog and volumes is direct.
+#[derive(Debug, Clone, Copy)]
+pub struct Lighting {
+    pub direct: Color,
+    pub indirect: Color,
+}
+impl Lighting {
+    pub fn total(&self) -> Color {
+        self.direct + self.indirect
+    }
+}
+impl Add for Lighting {
+    type Output = Lighting;
+
+    fn add(self, other: Lighting) -> Lighting {
+        Lighting {
+            direct: self.direct + other.direct,
+            indirect: self.indirect + other.indirect,
+        }
+    }
+}
+impl Mul<Color> for Lighting {
+    type Output = Lighting;
+
+    fn mul(self, factor: Color) -> Lighting {
+        Lighting {
+            direct: self.direct * factor,
+            indirect: self.indirect * factor,
+        }
+    }
+}
+impl Mul<f32> for Lighting {
+    type Output = Lighting;
+
+    fn mul(self, factor: f32) -> Lighting {
+        Lighting {
+            direct: self.direct * factor,
+            indirect: self.indirect * factor,
+        }
+    }
+}
+
+const DARK: Lighting = Lighting {
+    direct: BLACK,
+    indirect: BLACK,
+};
+
+/// Computes the color of a pixel, tracing it once per wavelength sample in spectral mode.
+pub fn trace_pixel(scene: &Scene, x: u32, y: u32) -> Result<Color, RenderError> {
+    trace_sample(scene, x, y, (0.5, 0.5))
+}
+
+/// Like `trace_pixel`, through a point of the pixel given as in `Ray::create_prime_sample`.
+pub fn trace_sample(scene: &Scene,
+                    x: u32,
+                    y: u32,
+                    offset: (f64, f64))
+                    -> Result<Color, RenderError> {
+    let prime = match Ray::create_prime_sample(x, y, offset, scene) {
+        Some(ray) => ray,
+        None => return Ok(BLACK),
+    };
+    let hit = scene.trace(&prime)?;
+    Ok(primary_lighting(scene, &prime, hit.as_ref())?.total())
+}
+
+/// The light seen along a primary ray whose first hit has already been traced, once per
+/// wavelength sample in spectral mode.
+pub fn primary_lighting(scene: &Scene,
+                        prime: &Ray,
+                        intersection: Option<&Intersection>)
+                        -> Result<Lighting, RenderError> {
+    if scene.max_recursion_depth == 0 {
+        return Ok(DARK);
+    }
+    if scene.spectral.is_none() {
+        stats::count_ray(RayKind::Primary);
+        return shade(scene, prime, intersection, 0);
+    }
+    // The first hit is the same at every wavelength; only the rays after it bend differently.
+    let mut lighting = DARK;
+    for &(wavelength, weight) in scene.wavelengths() {
+        let ray = Ray { wavelength: Some(wavelength), ..*prime };
+        stats::count_ray(RayKind::Primary);
+        lighting = lighting + shade(scene, &ray, intersection, 0)? * weight;
+    }
+    Ok(lighting)
+}
+
+/// Computes the colors of several pixels, tracing the primary rays of up to `PACKET_SIZE` of them
+/// at a time as a packet. Neighbouring pixels should be grouped together so that their rays are
+/// coherent. Spectral scenes are traced one pixel at a time.
+pub fn trace_pixels(scene: &Scene, pixels: &[(u32, u32)]) -> Result<Vec<Color>, RenderError> {
+    let mut colors = Vec::with_capacity(pixels.len());
+    for chunk in pixels.chunks(PACKET_SIZE) {
+        colors.extend_from_slice(&trace_packet(scene, chunk)?[..chunk.len()]);
+    }
+    Ok(colors)
+}
+
+/// Like `trace_pixels`, for at most `PACKET_SIZE` pixels, without allocating. Only the colors of
+/// the given pixels are meaningful.
+pub fn trace_packet(scene: &Scene,
+                    pixels: &[(u32, u32)])
+                    -> Result<[Color; PACKET_SIZE], RenderError> {
+    let mut colors = [BLACK; PACKET_SIZE];
+    if scene.spectral.is_some() || scene.max_recursion_depth == 0 {
+        for (color, &(x, y)) in colors.iter_mut().zip(pixels) {
+            *color = trace_pixel(scene, x, y)?;
+        }
+        return Ok(colors);
+    }
+    let packet = RayPacket::create_prime(pixels, scene);
+    let hits = scene.trace_packet(&packet)?;
+    for (lane, hit) in hits.iter().enumerate() {
+        // Inactive lanes are pixels that the projection does not cover, or no pixel at all.
+        if packet.active[lane] {
+            stats::count_ray(RayKind::Primary);
+            colors[lane] = shade(scene, &packet.ray(lane), hit.as_ref(), 0)?.total();
+        }
+    }
+    Ok(colors)
+}
+
+pub fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Result<Color, RenderError> {
+    Ok(cast_lighting(scene, ray, depth)?.total())
+}
+
+fn cast_lighting(scene: &Scene, ray: &Ray, depth: u32) -> Result<Lighting, RenderError> {
     if depth >= scene.max_recursion_depth {
-        return BLACK;
+        return Ok(DARK);
     }
 
-    let intersection = scene.trace(&ray);
-    intersection.map(|i| get_color(scene, &ray, &i, depth))
-        .unwrap_or(BLACK)
+    shade(scene, ray, scene.trace(ray)?.as_ref(), depth)
+}
+
+/// The color seen along a ray that has already been traced.
+fn shade(scene: &Scene,
+         ray: &Ray,
+         intersection: Option<&Intersection>,
+         depth: u32)
+         -> Result<Lighting, RenderError> {
+    stats::count_depth(depth);
+    let (lighting, distance) = match intersection {
+        Some(i) => {
+            let mut lighting = Lighting {
+                direct: direct_color(scene, ray, i)?,
+                indirect: indirect_color(scene, ray, i, depth)?,
+            };
+            let hit = ray.origin + (ray.direction * i.distance);
+            let coverage = i.coverage(&hit);
+            if coverage < 1.0 {
+                stats::count_ray(RayKind::Continuation);
+                let behind = Ray::create_continuation(i.surface_normal(&hit),
+                                                      ray.direction,
+                                                      hit,
+                                                      scene.shadow_bias)
+                    .with_wavelength(ray.wavelength);
+                lighting = lighting * coverage +
+                           cast_lighting(scene, &behind, depth + 1)? * (1.0 - coverage);
+            }
+            (lighting, i.distance)
+        }
+        None => {
+            let background = Lighting {
+                direct: scene.background.color(&ray.direction),
+                indirect: BLACK,
+            };
+            (background, f64::INFINITY)
+        }
+    };
+    let (transmitted, scattered) = media::along(scene, ray, distance)?;
+    Ok(Lighting {
+        direct: lighting.direct * transmitted + scattered,
+        indirect: lighting.indirect * transmitted,
+    })
 }
@@ -0,0 +1,127 @@
+use checkpoint::CheckpointTile;
+use error::RenderError;
+use image::{DynamicImage, GenericImage, Pixel, Rgba};
+use rendering::{BLACK, trace_sample};
+use scene::{Color, Scene};
+use ViewBlock;
+
+fn default_min_samples() -> u32 {
+    4
+}
+
+fn default_max_samples() -> u32 {
+    64
+}
+
+/// Takes several samples spread over the area of each pixel instead of a single one through its
+/// center, and keeps adding samples only to the pixels whose color has not converged.
+///
+/// Every pixel first gets `min_samples` samples. Samples are then added one at a time to the
+/// pixels whose noise is above `threshold`, until they reach `max_samples`. The noise is the
+/// standard error of the mean brightness of the pixel, on the 0 to 1 scale of the output image,
+/// so a threshold of 0.004 is about one level of an 8-bit image.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AdaptiveSampling {
+    pub threshold: f32,
+    /// At least two samples are always taken, so that the noise can be estimated.
+    #[serde(default = "default_min_samples")]
+    pub min_samples: u32,
+    #[serde(default = "default_max_samples")]
+    pub max_samples: u32,
+}
+impl AdaptiveSampling {
+    pub fn render_tile(&self,
+                       tile: &ViewBlock,
+                       scene: &Scene)
+                       -> Result<CheckpointTile, RenderError> {
+        let max_samples = self.max_samples.max(1);
+        let min_samples = self.min_samples.max(2).min(max_samples);
+        let pixels = (tile.width * tile.height) as usize;
+        let mut accumulated = vec![BLACK; pixels];
+        let mut samples = vec![0; pixels];
+        let mut brightness = vec![Moments::default(); pixels];
+
+        let mut unconverged: Vec<usize> = (0..pixels).collect();
+        while !unconverged.is_empty() {
+            for &i in &unconverged {
+                let (x, y) = (i as u32 % tile.width, i as u32 / tile.width);
+                let offset = sample_offset(samples[i]);
+                let color = trace_sample(scene, tile.x + x, tile.y + y, offset)?;
+                accumulated[i] = accumulated[i] + color;
+                brightness[i].add(perceived_brightness(color));
+                samples[i] += 1;
+            }
+            unconverged.retain(|&i| {
+                samples[i] < min_samples ||
+                samples[i] < max_samples && brightness[i].standard_error() > self.threshold as f64
+            });
+        }
+        Ok(CheckpointTile {
+            tile: *tile,
+            samples,
+            accumulated,
+        })
+    }
+}
+
+/// The offset of a sample from the top-left corner of its pixel. Samples follow the R2
+/// low-discrepancy sequence, starting from the center of the pixel, so that any number of them
+/// covers the pixel evenly.
+pub fn sample_offset(index: u32) -> (f64, f64) {
+    // Inverses of the plastic number and of its square.
+    const A1: f64 = 0.754_877_666_246_692_7;
+    const A2: f64 = 0.569_840_290_998_053_2;
+    ((0.5 + A1 * index as f64).fract(), (0.5 + A2 * index as f64).fract())
+}
+
+/// The luma of the color once encoded in the output image, from 0 to 1.
+fn perceived_brightness(color: Color) -> f64 {
+    let rgba = color.clamp().to_rgba();
+    (0.2126 * rgba[0] as f64 + 0.7152 * rgba[1] as f64 + 0.0722 * rgba[2] as f64) / 255.0
+}
+
+/// Running sums from which the mean of a series of values and its standard error are estimated.
+#[derive(Debug, Clone, Copy, Default)]
+struct Moments {
+    count: u32,
+    sum: f64,
+    sum_squares: f64,
+}
+impl Moments {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.sum_squares += value * value;
+    }
+
+    fn standard_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.count as f64;
+        let mean = self.sum / n;
+        let variance = ((self.sum_squares / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        (variance / n).sqrt()
+    }
+}
+
+/// Shows how many samples were taken for each pixel, given row by row, from black for none
+/// through red and yellow to white for the most.
+pub fn heatmap_image(width: u32, height: u32, samples: &[u32]) -> DynamicImage {
+    let most = samples.iter().cloned().max().unwrap_or(0).max(1);
+    let mut image = DynamicImage::new_rgb8(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let fraction = samples[(y * width + x) as usize] as f32 / most as f32;
+            image.put_pixel(x, y, heat_color(fraction));
+        }
+    }
+    image
+}
+
+/// The color of a heatmap for a value from 0 to 1, from black through red and yellow to white.
+pub fn heat_color(fraction: f32) -> Rgba<u8> {
+    let t = fraction * 3.0;
+    let channel = |start: f32| ((t - start).clamp(0.0, 1.0) * 255.0) as u8;
+    Rgba::from_channels(channel(0.0), channel(1.0), channel(2.0), 255)
+}
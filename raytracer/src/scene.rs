@@ -1,8 +1,25 @@
 use point::Point;
 use vector::Vector3;
 use rendering::{Intersectable, Ray, TextureCoords};
+use background::Background;
+use media::{Fog, Volume, default_volume_steps};
+use spectral::{Dispersion, SpectralSettings};
+use instance::{Group, Instance, load_groups};
+use sdf::Sdf;
+use emitter::{self, Emitter};
+use photon::CausticSettings;
+use light_sampling::{LightSampling, LightsAt};
+use sampling::AdaptiveSampling;
+use camera::{Projection, Stereo};
+use error::RenderError;
+use packet::{PACKET_SIZE, PacketIntersectable, RayPacket};
+use stats;
+use std::collections::HashMap;
 use std::ops::{Add, Mul};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::fs::File;
+use std::io::Read;
 use image;
 use image::{DynamicImage, GenericImage, Pixel, Rgba};
 use std::fmt;
@@ -26,6 +43,19 @@ pub struct Color {
     pub blue: f32,
 }
 impl Color {
+    pub fn rgb(red: f32, green: f32, blue: f32) -> Color {
+        Color { red, green, blue }
+    }
+
+    pub fn gray(value: f32) -> Color {
+        Color::rgb(value, value, value)
+    }
+
+    /// The perceived brightness of the color.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
     pub fn clamp(&self) -> Color {
         Color {
             red: self.red.min(1.0).max(0.0),
@@ -90,40 +120,45 @@ impl Add for Color {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// An image mapped onto a surface. The image is shared between clones, so that many elements
+/// can use the same texture.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Texture {
     pub path: PathBuf,
 
     #[serde(skip_serializing, skip_deserializing, default = "dummy_texture")]
-    pub texture: DynamicImage,
+    pub texture: Arc<DynamicImage>,
 }
-fn dummy_texture() -> DynamicImage {
-    DynamicImage::new_rgb8(0, 0)
+fn dummy_texture() -> Arc<DynamicImage> {
+    Arc::new(DynamicImage::new_rgb8(0, 0))
+}
+impl Texture {
+    pub fn open(path: PathBuf) -> Result<Texture, String> {
+        match image::open(&path) {
+            Ok(img) => {
+                Ok(Texture {
+                    path,
+                    texture: Arc::new(img),
+                })
+            }
+            Err(_) => Err(format!("Unable to open texture file: {:?}", path)),
+        }
+    }
 }
 impl fmt::Debug for Texture {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Texture({:?})", self.path)
     }
 }
-fn load_texture<D>(deserializer: D) -> Result<Texture, D::Error>
+pub fn load_texture<D>(deserializer: D) -> Result<Texture, D::Error>
 where
     D: Deserializer,
 {
     let texture = Texture::deserialize(deserializer)?;
-    if let Ok(img) = image::open(texture.path.clone()) {
-        Ok(Texture {
-            path: texture.path,
-            texture: img,
-        })
-    } else {
-        Err(::serde::de::Error::custom(format!(
-            "Unable to open texture file: {:?}",
-            texture.path
-        )))
-    }
+    Texture::open(texture.path).map_err(::serde::de::Error::custom)
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum Coloration {
     Color(Color),
     Texture(#[serde(deserialize_with = "load_texture")] Texture),
@@ -140,16 +175,58 @@ fn wrap(val: f32, bound: u32) -> u32 {
     }
 }
 
+impl Texture {
+    /// Looks up the texel at the given coordinates, wrapping around at the edges.
+    pub fn color(&self, coords: &TextureCoords) -> Color {
+        Color::from_rgba(self.texel(coords))
+    }
+
+    /// The alpha channel of the texel at the given coordinates, from 0 for transparent to 1.
+    pub fn alpha(&self, coords: &TextureCoords) -> f32 {
+        self.texel(coords).data[3] as f32 / 255.0
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(*self.texture,
+                 DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgba8(_))
+    }
+
+    fn texel(&self, coords: &TextureCoords) -> Rgba<u8> {
+        let tex_x = wrap(coords.x, self.texture.width());
+        let tex_y = wrap(coords.y, self.texture.height());
+        self.texture.get_pixel(tex_x, tex_y)
+    }
+}
+
 impl Coloration {
     pub fn color(&self, coords: &TextureCoords) -> Color {
         match *self {
             Coloration::Color(ref c) => c.clone(),
-            Coloration::Texture(ref texture) => {
-                let tex_x = wrap(coords.x, texture.texture.width());
-                let tex_y = wrap(coords.y, texture.texture.height());
+            Coloration::Texture(ref texture) => texture.color(coords),
+        }
+    }
+}
 
-                Color::from_rgba(texture.texture.get_pixel(tex_x, tex_y))
+/// A metal, described by its complex index of refraction `n + ik` for each color channel.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Silver,
+    Aluminium,
+    Custom { n: Color, k: Color },
+}
+impl Metal {
+    /// The real and imaginary parts of the index of refraction.
+    pub fn index(&self) -> (Color, Color) {
+        match *self {
+            Metal::Gold => (Color::rgb(0.143, 0.374, 1.442), Color::rgb(3.983, 2.386, 1.603)),
+            Metal::Copper => (Color::rgb(0.200, 0.924, 1.102), Color::rgb(3.912, 2.452, 2.142)),
+            Metal::Silver => (Color::rgb(0.155, 0.117, 0.138), Color::rgb(4.828, 3.122, 2.147)),
+            Metal::Aluminium => {
+                (Color::rgb(1.657, 0.880, 0.521), Color::rgb(9.224, 6.270, 4.837))
             }
+            Metal::Custom { n, k } => (n, k),
         }
     }
 }
@@ -158,14 +235,152 @@ impl Coloration {
 pub enum SurfaceType {
     Diffuse,
     Reflective { reflectivity: f32 },
-    Refractive { index: f32, transparency: f32 },
+    /// A polished metal, which reflects light like a mirror, tinted by the Fresnel reflectance
+    /// of the metal at each angle. The coloration and albedo of the material are not used.
+    Metal(Metal),
+    Refractive {
+        index: f32,
+        transparency: f32,
+        /// Makes the index depend on the wavelength in spectral mode; `index` is used otherwise.
+        #[serde(default)]
+        dispersion: Option<Dispersion>,
+    },
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Light given off by a surface, in the same units as the light the surface reflects. Emissive
+/// spheres and triangles, including those of instances, light the other surfaces as well; emissive
+/// planes and SDF elements only glow.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Emission {
+    pub color: Color,
+    pub strength: f32,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+/// Texels whose alpha is below this are cut out of the surface.
+const ALPHA_CUTOFF: f32 = 0.5;
+
+/// Number of cut out surfaces a ray can pass through before it is treated as a miss.
+const MAX_CUTOUTS: u32 = 64;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Material {
     pub coloration: Coloration,
     pub albedo: f32,
     pub surface: SurfaceType,
+    #[serde(default)]
+    pub emission: Option<Emission>,
+    /// The fraction of the light hitting the surface that it stops, the rest passing straight
+    /// through it. Independently of it, texels whose alpha is below one half are cut out of the
+    /// surface entirely, as if it had a hole there.
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+}
+/// Albedo of the material presets, that of a mid-grey surface.
+const DEFAULT_ALBEDO: f32 = 0.18;
+
+impl Material {
+    pub fn diffuse(color: Color) -> Material {
+        Material {
+            coloration: Coloration::Color(color),
+            albedo: DEFAULT_ALBEDO,
+            surface: SurfaceType::Diffuse,
+            emission: None,
+            opacity: 1.0,
+        }
+    }
+
+    pub fn textured(texture: Texture) -> Material {
+        Material {
+            coloration: Coloration::Texture(texture),
+            ..Material::diffuse(Color::gray(1.0))
+        }
+    }
+
+    /// A surface reflecting the given fraction of the light like a mirror, and diffusing the rest
+    /// with its color.
+    pub fn mirror(color: Color, reflectivity: f32) -> Material {
+        Material {
+            surface: SurfaceType::Reflective { reflectivity },
+            ..Material::diffuse(color)
+        }
+    }
+
+    pub fn metal(metal: Metal) -> Material {
+        Material {
+            surface: SurfaceType::Metal(metal),
+            ..Material::diffuse(Color::gray(1.0))
+        }
+    }
+
+    /// A clear, colorless transparent material with the given index of refraction.
+    pub fn glass(index: f32) -> Material {
+        Material {
+            surface: SurfaceType::Refractive {
+                index,
+                transparency: 1.0,
+                dispersion: None,
+            },
+            ..Material::diffuse(Color::gray(1.0))
+        }
+    }
+
+    /// A diffuse surface that also gives off light of its own color.
+    pub fn emissive(color: Color, strength: f32) -> Material {
+        Material::diffuse(color).with_emission(color, strength)
+    }
+
+    pub fn with_albedo(self, albedo: f32) -> Material {
+        Material { albedo, ..self }
+    }
+
+    pub fn with_opacity(self, opacity: f32) -> Material {
+        Material { opacity, ..self }
+    }
+
+    pub fn with_emission(self, color: Color, strength: f32) -> Material {
+        Material {
+            emission: Some(Emission { color, strength }),
+            ..self
+        }
+    }
+
+    /// The radiance emitted by the surface, which is black for non-emissive materials.
+    pub fn emitted(&self) -> Color {
+        match self.emission {
+            Some(ref e) => e.color * e.strength,
+            None => Color {
+                red: 0.0,
+                green: 0.0,
+                blue: 0.0,
+            },
+        }
+    }
+
+    /// Whether the surface stops all the light hitting it, everywhere.
+    pub fn is_opaque(&self) -> bool {
+        self.opacity >= 1.0 &&
+        match self.coloration {
+            Coloration::Color(_) => true,
+            Coloration::Texture(ref texture) => !texture.has_alpha(),
+        }
+    }
+
+    /// The fraction of the light that the surface stops at the given texture coordinates, which
+    /// is zero where it is cut out.
+    pub fn coverage(&self, coords: &TextureCoords) -> f32 {
+        match self.coloration {
+            Coloration::Texture(ref texture) if texture.alpha(coords) < ALPHA_CUTOFF => 0.0,
+            _ => self.opacity.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.as_ref().is_some_and(|e| e.strength > 0.0)
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -178,30 +393,80 @@ pub struct Sphere {
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Plane {
     pub origin: Point,
+    /// Points away from the side the plane is visible from, so a floor has a normal of
+    /// `(0, -1, 0)`.
     #[serde(deserialize_with = "Vector3::deserialize_normalized")]
     pub normal: Vector3,
     pub material: Material,
 }
 
+/// A triangle, with optional per-vertex normals and texture coordinates that are interpolated
+/// across its surface. The front face is the one from which the vertices appear
+/// counter-clockwise.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Triangle {
+    pub vertices: [Point; 3],
+    #[serde(default)]
+    pub normals: Option<[Vector3; 3]>,
+    #[serde(default)]
+    pub tex_coords: Option<[[f32; 2]; 3]>,
+    pub material: Material,
+}
+impl Triangle {
+    /// The normal of the triangle's plane, on the front side.
+    pub fn geometric_normal(&self) -> Vector3 {
+        let [a, b, c] = self.vertices;
+        (b - a).cross(&(c - a)).normalize()
+    }
+
+    /// The barycentric coordinates of a point on the triangle, i.e. the weights of each vertex.
+    pub fn barycentric(&self, point: &Point) -> [f64; 3] {
+        let [a, b, c] = self.vertices;
+        let (e1, e2, p) = (b - a, c - a, *point - a);
+        let (d11, d12, d22) = (e1.dot(&e1), e1.dot(&e2), e2.dot(&e2));
+        let (dp1, dp2) = (p.dot(&e1), p.dot(&e2));
+        let denom = d11 * d22 - d12 * d12;
+        let v = (d22 * dp1 - d12 * dp2) / denom;
+        let w = (d11 * dp2 - d12 * dp1) / denom;
+        [1.0 - v - w, v, w]
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
+    Triangle(Triangle),
+    Sdf(Sdf),
+    Instance(Instance),
 }
 impl Element {
-    pub fn material(&self) -> &Material {
+    /// The material of the element, or `None` for instances, which have no material of their
+    /// own. The material of the group element that was hit is found through
+    /// `Intersection::material`.
+    pub fn material(&self) -> Option<&Material> {
         match *self {
-            Element::Sphere(ref s) => &s.material,
-            Element::Plane(ref p) => &p.material,
+            Element::Sphere(ref s) => Some(&s.material),
+            Element::Plane(ref p) => Some(&p.material),
+            Element::Triangle(ref t) => Some(&t.material),
+            Element::Sdf(ref s) => Some(&s.material),
+            Element::Instance(_) => None,
         }
     }
 
-    pub fn material_mut(&mut self) -> &mut Material {
+    pub fn material_mut(&mut self) -> Option<&mut Material> {
         match *self {
-            Element::Sphere(ref mut s) => &mut s.material,
-            Element::Plane(ref mut p) => &mut p.material,
+            Element::Sphere(ref mut s) => Some(&mut s.material),
+            Element::Plane(ref mut p) => Some(&mut p.material),
+            Element::Triangle(ref mut t) => Some(&mut t.material),
+            Element::Sdf(ref mut s) => Some(&mut s.material),
+            Element::Instance(_) => None,
         }
     }
+
+    pub fn is_instance(&self) -> bool {
+        matches!(*self, Element::Instance(_))
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -219,16 +484,190 @@ pub struct SphericalLight {
     pub intensity: f32,
 }
 
+/// A tabulated angular intensity profile, in the spirit of IES photometric files.
+///
+/// The file lists one `angle multiplier` pair per line, where the angle is measured in degrees
+/// from the light's axis. Blank lines and lines starting with `#` are ignored. The multiplier is
+/// interpolated linearly between entries and held constant beyond the first and last ones.
+#[derive(Serialize, Deserialize)]
+pub struct LightProfile {
+    path: PathBuf,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    samples: Vec<(f64, f32)>,
+}
+impl fmt::Debug for LightProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LightProfile({:?})", self.path)
+    }
+}
+impl LightProfile {
+    /// A profile from `angle, multiplier` pairs in any order, recorded as having been read from
+    /// `path`. There must be at least one pair, and none may be NaN.
+    pub fn new(path: PathBuf, mut samples: Vec<(f64, f32)>) -> Result<LightProfile, String> {
+        if samples.is_empty() {
+            return Err(format!("Light profile {:?} has no entries", path));
+        }
+        if samples.iter().any(|&(a, m)| f64::is_nan(a) || f32::is_nan(m)) {
+            return Err(format!("Light profile {:?} has an entry that is not a number", path));
+        }
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(LightProfile { path, samples })
+    }
+
+    pub fn open(path: PathBuf) -> Result<LightProfile, String> {
+        let mut contents = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| format!("Unable to open light profile {:?}: {}", path, e))?;
+
+        let mut samples: Vec<(f64, f32)> = vec![];
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match values.as_slice() {
+                [angle, multiplier] => {
+                    match (angle.parse(), multiplier.parse()) {
+                        (Ok(a), Ok(m)) if !f64::is_nan(a) && !f32::is_nan(m) => Some((a, m)),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match parsed {
+                Some(sample) => samples.push(sample),
+                None => {
+                    return Err(format!("Invalid light profile entry on line {} of {:?}",
+                                       number + 1,
+                                       path))
+                }
+            }
+        }
+        LightProfile::new(path, samples)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn multiplier(&self, angle: f64) -> f32 {
+        let first = self.samples[0];
+        let last = self.samples[self.samples.len() - 1];
+        if angle <= first.0 {
+            return first.1;
+        }
+        if angle >= last.0 {
+            return last.1;
+        }
+        // A NaN angle compares false against every entry and falls back on the last one.
+        let next = match self.samples.iter().position(|s| s.0 > angle) {
+            Some(next) => next,
+            None => return last.1,
+        };
+        let (a0, m0) = self.samples[next - 1];
+        let (a1, m1) = self.samples[next];
+        let t = ((angle - a0) / (a1 - a0)) as f32;
+        m0 * (1.0 - t) + m1 * t
+    }
+}
+fn load_light_profile<D>(deserializer: D) -> Result<Option<LightProfile>, D::Error>
+where
+    D: Deserializer,
+{
+    match Option::<LightProfile>::deserialize(deserializer)? {
+        Some(profile) => {
+            LightProfile::open(profile.path).map(Some).map_err(::serde::de::Error::custom)
+        }
+        None => Ok(None),
+    }
+}
+
+/// A point light that only shines within a cone. The intensity falls off smoothly between the
+/// inner and outer cone angles, which are half-angles in degrees measured from `direction`.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SpotLight {
+    pub position: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub direction: Vector3,
+    pub color: Color,
+    pub intensity: f32,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    #[serde(default, deserialize_with = "load_light_profile")]
+    pub profile: Option<LightProfile>,
+}
+impl SpotLight {
+    /// The fraction of the light's intensity emitted towards the given point.
+    pub fn falloff(&self, point: &Point) -> f32 {
+        let to_point = (*point - self.position).normalize();
+        let cos_angle = to_point.dot(&self.direction).clamp(-1.0, 1.0);
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+        let cone = if cos_angle >= cos_inner {
+            1.0
+        } else if cos_angle <= cos_outer {
+            0.0
+        } else {
+            let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+            (t * t * (3.0 - 2.0 * t)) as f32
+        };
+        match self.profile {
+            Some(ref profile) => cone * profile.multiplier(cos_angle.acos().to_degrees()),
+            None => cone,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub enum Light {
     Directional(DirectionalLight),
     Spherical(SphericalLight),
+    Spot(SpotLight),
 }
 impl Light {
+    pub fn directional(direction: Vector3, color: Color, intensity: f32) -> Light {
+        Light::Directional(DirectionalLight {
+            direction: direction.normalize(),
+            color,
+            intensity,
+        })
+    }
+
+    pub fn point(position: Point, color: Color, intensity: f32) -> Light {
+        Light::Spherical(SphericalLight {
+            position,
+            color,
+            intensity,
+        })
+    }
+
+    /// A spot light at `position` aimed at `target`, with the cone angles in degrees.
+    pub fn spot(position: Point,
+                target: Point,
+                color: Color,
+                intensity: f32,
+                inner_angle: f64,
+                outer_angle: f64)
+                -> Light {
+        Light::Spot(SpotLight {
+            position,
+            direction: (target - position).normalize(),
+            color,
+            intensity,
+            inner_angle,
+            outer_angle,
+            profile: None,
+        })
+    }
+
     pub fn color(&self) -> Color {
         match *self {
             Light::Directional(ref d) => d.color,
             Light::Spherical(ref s) => s.color,
+            Light::Spot(ref s) => s.color,
         }
     }
 
@@ -236,6 +675,7 @@ impl Light {
         match *self {
             Light::Directional(ref d) => -d.direction,
             Light::Spherical(ref s) => (s.position - *hit_point).normalize(),
+            Light::Spot(ref s) => (s.position - *hit_point).normalize(),
         }
     }
 
@@ -246,6 +686,10 @@ impl Light {
                 let r2 = (s.position - *hit_point).norm() as f32;
                 s.intensity / (4.0 * ::std::f32::consts::PI * r2)
             }
+            Light::Spot(ref s) => {
+                let r2 = (s.position - *hit_point).norm() as f32;
+                s.intensity * s.falloff(hit_point) / (4.0 * ::std::f32::consts::PI * r2)
+            }
         }
     }
 
@@ -253,6 +697,7 @@ impl Light {
         match *self {
             Light::Directional(_) => ::std::f64::INFINITY,
             Light::Spherical(ref s) => (s.position - *hit_point).length(),
+            Light::Spot(ref s) => (s.position - *hit_point).length(),
         }
     }
 }
@@ -262,38 +707,352 @@ pub struct Scene {
     pub width: u32,
     pub height: u32,
     pub fov: f64,
+    #[serde(default)]
+    pub projection: Projection,
+    /// Renders a side-by-side stereo pair instead of a single view.
+    #[serde(default)]
+    pub stereo: Option<Stereo>,
     pub elements: Vec<Element>,
     pub lights: Vec<Light>,
+    #[serde(default)]
+    pub background: Background,
+    #[serde(default)]
+    pub fog: Option<Fog>,
+    #[serde(default)]
+    pub volumes: Vec<Volume>,
+    /// Number of steps used to integrate the light scattered by fog and volumes along a ray.
+    #[serde(default = "default_volume_steps")]
+    pub volume_steps: u32,
+    #[serde(default)]
+    pub spectral: Option<SpectralSettings>,
+    #[serde(default)]
+    pub caustics: Option<CausticSettings>,
+    /// Takes more samples in the pixels that need them instead of one through each pixel.
+    #[serde(default)]
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    #[serde(default)]
+    pub light_sampling: Option<LightSampling>,
+    /// Named geometry that can be placed any number of times with `Instance` elements.
+    #[serde(default, deserialize_with = "load_groups")]
+    pub groups: HashMap<String, Group>,
 
     pub shadow_bias: f64,
     pub max_recursion_depth: u32,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub cache: SceneCache,
+}
+
+/// Data derived from the rest of a scene, worked out the first time it is needed. It is not
+/// updated when the scene changes, so a scene that is changed after being rendered should be
+/// given a new `SceneCache::default()`.
+#[derive(Debug, Default)]
+pub struct SceneCache {
+    emitters: OnceLock<Vec<Emitter>>,
+    wavelengths: OnceLock<Vec<(f32, Color)>>,
 }
 
 pub struct Intersection<'a> {
     pub distance: f64,
     pub element: &'a Element,
+    /// The material of `element`.
+    pub material: &'a Material,
+    /// The instance through which `element` was hit, when it belongs to a group.
+    pub instance: Option<&'a Instance>,
+    /// Index in `Scene::elements` of the element that was hit, or of its instance.
+    pub index: usize,
 
     //Prevent outside code from constructing this; should use the new method and check the distance.
     _secret: (),
 }
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
+    pub fn new<'b>(distance: f64,
+                   element: &'b Element,
+                   index: usize)
+                   -> Result<Intersection<'b>, RenderError> {
         if !distance.is_finite() {
-            panic!("Intersection must have a finite distance.");
+            return Err(RenderError::NonFiniteDistance(distance));
         }
-        Intersection {
+        let material = element.material().ok_or(RenderError::UnresolvedInstance)?;
+        Ok(Intersection {
             distance: distance,
             element: element,
+            material,
+            instance: None,
+            index,
             _secret: (),
+        })
+    }
+
+    pub fn instanced<'b>(distance: f64,
+                         element: &'b Element,
+                         instance: &'b Instance,
+                         index: usize)
+                         -> Result<Intersection<'b>, RenderError> {
+        Intersection::new(distance, element, index)
+            .map(|i| Intersection { instance: Some(instance), ..i })
+    }
+
+    /// The world space normal at a world space hit point.
+    pub fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        match self.instance {
+            Some(instance) => {
+                let transform = &instance.transform;
+                let normal = self.element.surface_normal(&transform.point_to_object(hit_point));
+                transform.normal_to_world(&normal)
+            }
+            None => self.element.surface_normal(hit_point),
+        }
+    }
+
+    pub fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        match self.instance {
+            Some(instance) => {
+                self.element.texture_coords(&instance.transform.point_to_object(hit_point))
+            }
+            None => self.element.texture_coords(hit_point),
+        }
+    }
+
+    /// The fraction of the light that the surface stops at a world space hit point.
+    pub fn coverage(&self, hit_point: &Point) -> f32 {
+        let material = self.material;
+        if material.is_opaque() {
+            1.0
+        } else {
+            material.coverage(&self.texture_coords(hit_point))
         }
     }
 }
 
 impl Scene {
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
+    /// The emissive elements that act as light sources, including those inside instances.
+    pub fn emitters(&self) -> &[Emitter] {
+        self.cache.emitters.get_or_init(|| emitter::emitters(self).collect())
+    }
+
+    /// The wavelengths traced for each pixel in spectral mode, with the color each contributes,
+    /// or none if the scene is not spectral.
+    pub fn wavelengths(&self) -> &[(f32, Color)] {
+        self.cache.wavelengths.get_or_init(|| {
+            self.spectral.as_ref().map_or_else(Vec::new, SpectralSettings::wavelengths)
+        })
+    }
+
+    /// Checks the parts of the scene that deserialization cannot, so that rendering does not
+    /// fail halfway through.
+    pub fn validate(&self) -> Result<(), RenderError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(RenderError::EmptyImage {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if self.width < self.height {
+            return Err(RenderError::UnsupportedAspectRatio {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if !self.projection.supports_fov(self.fov) {
+            return Err(RenderError::InvalidFieldOfView(self.fov));
+        }
+        for element in &self.elements {
+            if let Element::Instance(ref instance) = *element {
+                if !self.groups.contains_key(&instance.group) {
+                    return Err(RenderError::UnknownGroup(instance.group.clone()));
+                }
+                if !instance.transform.matrix().is_invertible() {
+                    return Err(RenderError::SingularTransform(instance.group.clone()));
+                }
+            }
+        }
+        for (name, group) in &self.groups {
+            if group.elements.iter().any(Element::is_instance) {
+                return Err(RenderError::NestedInstance(name.clone()));
+            }
+        }
+        if let Some(material) = self.materials().find(|m| !(0.0..=1.0).contains(&m.opacity)) {
+            return Err(RenderError::InvalidOpacity(material.opacity));
+        }
+        if self.spectral.is_some() {
+            for material in self.materials() {
+                if let SurfaceType::Refractive { dispersion: Some(ref dispersion), .. } =
+                       material.surface {
+                    if let Some(wavelength) = dispersion.invalid_wavelength(self.wavelengths()) {
+                        return Err(RenderError::InvalidDispersion(wavelength));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The materials of every element, including those of groups.
+    fn materials(&self) -> impl Iterator<Item = &Material> {
         self.elements
             .iter()
-            .filter_map(|e| e.intersect(ray).map(|d| Intersection::new(d, e)))
-            .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+            .chain(self.groups.values().flat_map(|g| g.elements.iter()))
+            .filter_map(Element::material)
+    }
+
+    /// The lights to shade a point with, each with the factor to scale its light by. Every light,
+    /// including those standing in for the background, is used as is unless the scene samples
+    /// them.
+    pub fn lights_at(&self, point: &Point, normal: Option<&Vector3>) -> LightsAt<'_> {
+        match self.light_sampling {
+            Some(ref sampling) => LightsAt::Sampled(sampling.select(self, point, normal)),
+            None => LightsAt::All(self.lights.iter().chain(self.background.lights())),
+        }
+    }
+
+    /// Finds the closest intersection of the ray, passing through the parts of surfaces that are
+    /// cut out.
+    pub fn trace(&self, ray: &Ray) -> Result<Option<Intersection<'_>>, RenderError> {
+        let mut ray = Ray { ..*ray };
+        let mut travelled = 0.0;
+        for _ in 0..MAX_CUTOUTS {
+            let mut intersection = match self.trace_surfaces(&ray)? {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+            let hit = ray.origin + (ray.direction * intersection.distance);
+            if intersection.coverage(&hit) > 0.0 {
+                intersection.distance += travelled;
+                return Ok(Some(intersection));
+            }
+            travelled += intersection.distance;
+            stats::count_ray(stats::RayKind::Continuation);
+            ray = Ray::create_continuation(intersection.surface_normal(&hit),
+                                           ray.direction,
+                                           hit,
+                                           self.shadow_bias)
+                .with_wavelength(ray.wavelength);
+        }
+        Ok(None)
+    }
+
+    /// Like `trace`, including the hits on cut out parts of surfaces.
+    fn trace_surfaces(&self, ray: &Ray) -> Result<Option<Intersection<'_>>, RenderError> {
+        let mut closest: Option<Intersection> = None;
+        // Counted once per call, as the statistics are too slow to update for every test.
+        let mut tests = 0;
+        for (index, element) in self.elements.iter().enumerate() {
+            let hit = match *element {
+                Element::Instance(ref instance) => {
+                    self.trace_instance(instance, index, ray, &mut tests)?
+                }
+                _ => {
+                    tests += 1;
+                    match element.intersect(ray) {
+                        Some(distance) => Some(Intersection::new(distance, element, index)?),
+                        None => None,
+                    }
+                }
+            };
+            keep_closest(&mut closest, hit);
+        }
+        stats::count_intersection_tests(tests);
+        Ok(closest)
+    }
+
+    /// Traces the ray through the shared acceleration structure of the instance's group, in the
+    /// group's own space.
+    fn trace_instance<'a>(&'a self,
+                          instance: &'a Instance,
+                          index: usize,
+                          ray: &Ray,
+                          tests: &mut u64)
+                          -> Result<Option<Intersection<'a>>, RenderError> {
+        let group = self.group(instance)?;
+        let (object_ray, scale) = instance.transform.ray_to_object(ray);
+        match group.trace(&object_ray, tests) {
+            Some((distance, element)) => {
+                Intersection::instanced(distance * scale, element, instance, index).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn group(&self, instance: &Instance) -> Result<&Group, RenderError> {
+        self.groups
+            .get(&instance.group)
+            .ok_or_else(|| RenderError::UnknownGroup(instance.group.clone()))
+    }
+
+    /// Finds the closest intersection of every ray of the packet, like `trace` does for one ray.
+    pub fn trace_packet(&self,
+                        packet: &RayPacket)
+                        -> Result<[Option<Intersection<'_>>; PACKET_SIZE], RenderError> {
+        let mut closest: [Option<Intersection>; PACKET_SIZE] = Default::default();
+        let lanes = packet.active.iter().filter(|&&a| a).count() as u64;
+        let mut tests = 0;
+        for (index, element) in self.elements.iter().enumerate() {
+            let mut hits: [Option<Intersection>; PACKET_SIZE] = Default::default();
+            match *element {
+                Element::Instance(ref instance) => {
+                    hits = self.trace_instance_packet(instance, index, packet, &mut tests)?;
+                }
+                _ => {
+                    tests += lanes;
+                    let distances = element.intersect_packet(packet);
+                    for (hit, &distance) in hits.iter_mut().zip(distances.iter()) {
+                        // Misses are reported as an infinite distance.
+                        if distance != f64::INFINITY {
+                            *hit = Some(Intersection::new(distance, element, index)?);
+                        }
+                    }
+                }
+            }
+            for (hit, closest) in hits.iter_mut().zip(closest.iter_mut()) {
+                keep_closest(closest, hit.take());
+            }
+        }
+        stats::count_intersection_tests(tests);
+        // Rays that hit cut out parts of surfaces carry on one at a time.
+        for (lane, hit) in closest.iter_mut().enumerate() {
+            let ray = packet.ray(lane);
+            let cut_out = match *hit {
+                Some(ref i) => i.coverage(&(ray.origin + (ray.direction * i.distance))) <= 0.0,
+                None => false,
+            };
+            if cut_out {
+                *hit = self.trace(&ray)?;
+            }
+        }
+        Ok(closest)
+    }
+
+    fn trace_instance_packet<'a>(&'a self,
+                                 instance: &'a Instance,
+                                 index: usize,
+                                 packet: &RayPacket,
+                                 tests: &mut u64)
+                                 -> Result<[Option<Intersection<'a>>; PACKET_SIZE], RenderError> {
+        let group = self.group(instance)?;
+        let mut scales = [0.0; PACKET_SIZE];
+        let rays: [Ray; PACKET_SIZE] = ::std::array::from_fn(|i| {
+            let (ray, scale) = instance.transform.ray_to_object(&packet.ray(i));
+            scales[i] = scale;
+            ray
+        });
+        let object_packet = RayPacket { active: packet.active, ..RayPacket::new(&rays) };
+        let hits = group.trace_packet(&object_packet, tests);
+        let mut intersections: [Option<Intersection>; PACKET_SIZE] = Default::default();
+        for (i, hit) in hits.iter().enumerate() {
+            if let Some((distance, element)) = *hit {
+                intersections[i] =
+                    Some(Intersection::instanced(distance * scales[i], element, instance, index)?);
+            }
+        }
+        Ok(intersections)
+    }
+}
+
+fn keep_closest<'a>(closest: &mut Option<Intersection<'a>>, hit: Option<Intersection<'a>>) {
+    if let Some(hit) = hit {
+        if closest.as_ref().is_none_or(|c| hit.distance < c.distance) {
+            *closest = Some(hit);
+        }
     }
 }
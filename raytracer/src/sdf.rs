@@ -0,0 +1,331 @@
+use bvh::BoundingBox;
+use point::Point;
+use rendering::{Intersectable, Ray, TextureCoords};
+use scene::Material;
+use vector::Vector3;
+use std::f64;
+
+/// A signed distance function, built from primitives centered on the origin and the operations
+/// that combine and deform them. The distance is negative inside the surface, and never more
+/// than the distance to the surface, so that rays can safely advance by it.
+#[derive(Deserialize, Serialize, Debug)]
+pub enum Shape {
+    Sphere { radius: f64 },
+    Cuboid { half_extents: Vector3 },
+    /// A torus lying in the xz plane.
+    Torus { major_radius: f64, minor_radius: f64 },
+    /// A capped cylinder along the y axis.
+    Cylinder { radius: f64, half_height: f64 },
+    Union(Vec<Shape>),
+    Intersection(Vec<Shape>),
+    /// The `base` shape with the `cut` shape carved out of it.
+    Subtraction { base: Box<Shape>, cut: Box<Shape> },
+    /// A union blending the shapes together where they are closer than `smoothness`.
+    SmoothUnion { shapes: Vec<Shape>, smoothness: f64 },
+    /// Grows the shape by `radius`, which rounds its edges.
+    Round { shape: Box<Shape>, radius: f64 },
+    /// Repeats the shape forever along every axis with a non-zero period. The shape should fit
+    /// within one period.
+    Repeat { shape: Box<Shape>, period: Vector3 },
+    /// Twists the shape around the y axis by `rate` degrees per unit of height.
+    Twist { shape: Box<Shape>, rate: f64 },
+    Translate { shape: Box<Shape>, offset: Vector3 },
+    Scale { shape: Box<Shape>, factor: f64 },
+}
+impl Shape {
+    pub fn distance(&self, p: &Point) -> f64 {
+        match *self {
+            Shape::Sphere { radius } => length(p.x, p.y, p.z) - radius,
+            Shape::Cuboid { half_extents } => {
+                let q = [p.x.abs() - half_extents.x,
+                         p.y.abs() - half_extents.y,
+                         p.z.abs() - half_extents.z];
+                let outside = length(q[0].max(0.0), q[1].max(0.0), q[2].max(0.0));
+                let inside = q[0].max(q[1]).max(q[2]).min(0.0);
+                outside + inside
+            }
+            Shape::Torus { major_radius, minor_radius } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            Shape::Cylinder { radius, half_height } => {
+                let dr = (p.x * p.x + p.z * p.z).sqrt() - radius;
+                let dy = p.y.abs() - half_height;
+                let outside = dr.max(0.0).hypot(dy.max(0.0));
+                outside + dr.max(dy).min(0.0)
+            }
+            Shape::Union(ref shapes) => {
+                shapes.iter().map(|s| s.distance(p)).fold(f64::INFINITY, f64::min)
+            }
+            Shape::Intersection(ref shapes) => {
+                shapes.iter().map(|s| s.distance(p)).fold(f64::NEG_INFINITY, f64::max)
+            }
+            Shape::Subtraction { ref base, ref cut } => base.distance(p).max(-cut.distance(p)),
+            Shape::SmoothUnion { ref shapes, smoothness } => {
+                shapes.iter()
+                    .map(|s| s.distance(p))
+                    .fold(f64::INFINITY, |a, b| smooth_min(a, b, smoothness))
+            }
+            Shape::Round { ref shape, radius } => shape.distance(p) - radius,
+            Shape::Repeat { ref shape, period } => {
+                let wrap = |x: f64, period: f64| if period > 0.0 {
+                    x - period * (x / period).round()
+                } else {
+                    x
+                };
+                shape.distance(&Point {
+                    x: wrap(p.x, period.x),
+                    y: wrap(p.y, period.y),
+                    z: wrap(p.z, period.z),
+                })
+            }
+            Shape::Twist { ref shape, rate } => {
+                let rate = rate.to_radians();
+                let (sin, cos) = (-rate * p.y).sin_cos();
+                let untwisted = Point {
+                    x: cos * p.x - sin * p.z,
+                    y: p.y,
+                    z: sin * p.x + cos * p.z,
+                };
+                // Twisting stretches space more the further it is from the axis, so the distance
+                // is shrunk accordingly to remain a safe step.
+                let radius = (p.x * p.x + p.z * p.z).sqrt();
+                shape.distance(&untwisted) / (1.0 + (rate * radius).abs())
+            }
+            Shape::Translate { ref shape, offset } => shape.distance(&(*p - offset)),
+            Shape::Scale { ref shape, factor } => {
+                let scaled = Point {
+                    x: p.x / factor,
+                    y: p.y / factor,
+                    z: p.z / factor,
+                };
+                shape.distance(&scaled) * factor
+            }
+        }
+    }
+
+    /// The bounds of the shape, or `None` if it is infinite.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        match *self {
+            Shape::Sphere { radius } => Some(centered_box(radius, radius, radius)),
+            Shape::Cuboid { half_extents } => {
+                Some(centered_box(half_extents.x, half_extents.y, half_extents.z))
+            }
+            Shape::Torus { major_radius, minor_radius } => {
+                let r = major_radius + minor_radius;
+                Some(centered_box(r, minor_radius, r))
+            }
+            Shape::Cylinder { radius, half_height } => {
+                Some(centered_box(radius, half_height, radius))
+            }
+            Shape::Union(ref shapes) => union_bounds(shapes),
+            Shape::Intersection(ref shapes) => {
+                // Any bounded shape bounds the intersection, and the tightest is their overlap.
+                shapes.iter().filter_map(Shape::bounding_box).fold(None, |acc, b| {
+                    Some(acc.map_or(b, |a: BoundingBox| {
+                        BoundingBox {
+                            min: Point {
+                                x: a.min.x.max(b.min.x),
+                                y: a.min.y.max(b.min.y),
+                                z: a.min.z.max(b.min.z),
+                            },
+                            max: Point {
+                                x: a.max.x.min(b.max.x),
+                                y: a.max.y.min(b.max.y),
+                                z: a.max.z.min(b.max.z),
+                            },
+                        }
+                    }))
+                })
+            }
+            Shape::Subtraction { ref base, .. } => base.bounding_box(),
+            // The smooth minimum is at most a quarter of the smoothness below the plain minimum.
+            Shape::SmoothUnion { ref shapes, smoothness } => {
+                union_bounds(shapes).map(|b| grow(&b, smoothness / 4.0))
+            }
+            Shape::Round { ref shape, radius } => shape.bounding_box().map(|b| grow(&b, radius)),
+            Shape::Repeat { ref shape, period } => {
+                if period.x > 0.0 || period.y > 0.0 || period.z > 0.0 {
+                    None
+                } else {
+                    shape.bounding_box()
+                }
+            }
+            Shape::Twist { ref shape, .. } => {
+                shape.bounding_box().map(|b| {
+                    let radius = b.corners()
+                        .iter()
+                        .map(|c| (c.x * c.x + c.z * c.z).sqrt())
+                        .fold(0.0, f64::max);
+                    BoundingBox {
+                        min: Point {
+                            x: -radius,
+                            y: b.min.y,
+                            z: -radius,
+                        },
+                        max: Point {
+                            x: radius,
+                            y: b.max.y,
+                            z: radius,
+                        },
+                    }
+                })
+            }
+            Shape::Translate { ref shape, offset } => {
+                shape.bounding_box().map(|b| {
+                    BoundingBox {
+                        min: b.min + offset,
+                        max: b.max + offset,
+                    }
+                })
+            }
+            Shape::Scale { ref shape, factor } => {
+                let scale = |p: Point| {
+                    Point {
+                        x: p.x * factor,
+                        y: p.y * factor,
+                        z: p.z * factor,
+                    }
+                };
+                shape.bounding_box().map(|b| {
+                    BoundingBox {
+                        min: scale(b.min),
+                        max: scale(b.max),
+                    }
+                })
+            }
+        }
+    }
+}
+
+fn length(x: f64, y: f64, z: f64) -> f64 {
+    (x * x + y * y + z * z).sqrt()
+}
+
+/// Polynomial smooth minimum, which blends `a` and `b` where they are within `k` of each other.
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 || a.is_infinite() || b.is_infinite() {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+fn centered_box(x: f64, y: f64, z: f64) -> BoundingBox {
+    BoundingBox {
+        min: Point { x: -x, y: -y, z: -z },
+        max: Point { x, y, z },
+    }
+}
+
+fn grow(b: &BoundingBox, amount: f64) -> BoundingBox {
+    let amount = Vector3::from_one(amount);
+    BoundingBox {
+        min: b.min - amount,
+        max: b.max + amount,
+    }
+}
+
+fn union_bounds(shapes: &[Shape]) -> Option<BoundingBox> {
+    shapes.iter().try_fold(BoundingBox::empty(), |a, s| s.bounding_box().map(|b| a.union(&b)))
+}
+
+fn default_max_steps() -> u32 {
+    256
+}
+
+fn default_tolerance() -> f64 {
+    1e-4
+}
+
+fn default_max_distance() -> f64 {
+    1000.0
+}
+
+fn default_step_scale() -> f64 {
+    1.0
+}
+
+/// An implicit surface, rendered by sphere tracing: rays advance by the distance to the surface
+/// until they are within `tolerance` of it.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Sdf {
+    pub shape: Shape,
+    pub material: Material,
+    /// Steps after which a ray that has not reached the surface is considered a miss.
+    #[serde(default = "default_max_steps")]
+    pub max_steps: u32,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// How far rays are marched when the shape is infinite, e.g. repeated.
+    #[serde(default = "default_max_distance")]
+    pub max_distance: f64,
+    /// Fraction of the distance that rays advance by at each step. Lowering it avoids
+    /// overshooting shapes whose distance is only approximate, such as strongly twisted ones.
+    #[serde(default = "default_step_scale")]
+    pub step_scale: f64,
+}
+impl Sdf {
+    /// The gradient of the distance function, estimated by central differences.
+    fn gradient(&self, p: &Point) -> Vector3 {
+        let h = self.tolerance;
+        let d = |x: f64, y: f64, z: f64| {
+            self.shape.distance(&Point {
+                x: p.x + x,
+                y: p.y + y,
+                z: p.z + z,
+            })
+        };
+        Vector3 {
+            x: d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+            y: d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+            z: d(0.0, 0.0, h) - d(0.0, 0.0, -h),
+        }
+    }
+}
+
+impl Intersectable for Sdf {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (mut t, t_max) = match self.shape.bounding_box() {
+            Some(bounds) => bounds.range(ray, f64::INFINITY)?,
+            None => (0.0, self.max_distance),
+        };
+        // Rays leaving the surface start within the tolerance of it, so hits are only accepted
+        // once the ray has been clear of the surface, which it was if it started outside the
+        // bounds. Rays may also start inside the shape, so they advance by the absolute distance.
+        let mut clear = t > 0.0;
+        for _ in 0..self.max_steps {
+            let distance = self.shape.distance(&(ray.origin + ray.direction * t)).abs();
+            if distance >= self.tolerance {
+                clear = true;
+            } else if clear {
+                return Some(t);
+            }
+            t += (distance * self.step_scale).max(self.tolerance);
+            if t > t_max {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        self.gradient(hit_point).normalize()
+    }
+
+    /// Box mapping: the surface is projected along the axis its normal is closest to.
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let n = self.gradient(hit_point);
+        let (u, v) = if n.x.abs() >= n.y.abs() && n.x.abs() >= n.z.abs() {
+            (hit_point.z, hit_point.y)
+        } else if n.y.abs() >= n.z.abs() {
+            (hit_point.x, hit_point.z)
+        } else {
+            (hit_point.x, hit_point.y)
+        };
+        TextureCoords {
+            x: u as f32,
+            y: v as f32,
+        }
+    }
+}
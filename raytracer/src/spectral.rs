@@ -0,0 +1,101 @@
+use background::xyz_to_rgb;
+use rendering::BLACK;
+use scene::Color;
+
+/// Shortest and longest wavelengths traced in spectral mode, in nanometers.
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 730.0;
+
+/// Enables spectral rendering: every pixel is traced once per wavelength sample, so that
+/// dispersive materials can bend each wavelength differently.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SpectralSettings {
+    pub samples: u32,
+}
+impl SpectralSettings {
+    /// The wavelengths to trace for each pixel, evenly spread over the visible spectrum, paired
+    /// with the color each of them contributes. The weights of all samples add up to white.
+    /// `Scene::wavelengths` keeps the result, which is the same for every pixel.
+    pub fn wavelengths(&self) -> Vec<(f32, Color)> {
+        let samples = self.samples.max(1);
+        let step = (MAX_WAVELENGTH - MIN_WAVELENGTH) / samples as f32;
+        let mut wavelengths: Vec<(f32, Color)> = (0..samples)
+            .map(|i| {
+                let wavelength = MIN_WAVELENGTH + (i as f32 + 0.5) * step;
+                let (x, y, z) = cie_xyz(wavelength);
+                (wavelength, xyz_to_rgb(x as f64, y as f64, z as f64))
+            })
+            .collect();
+
+        let total = wavelengths.iter().fold(BLACK, |sum, w| sum + w.1);
+        let normalize = |value: f32, total: f32| if total > 0.0 { value / total } else { 0.0 };
+        for w in &mut wavelengths {
+            w.1 = Color {
+                red: normalize(w.1.red, total.red),
+                green: normalize(w.1.green, total.green),
+                blue: normalize(w.1.blue, total.blue),
+            };
+        }
+        wavelengths
+    }
+}
+
+/// How the index of refraction of a material varies with wavelength. Both formulas take the
+/// wavelength in micrometers. Scenes whose coefficients give no finite, positive index at one of
+/// the traced wavelengths, or have a Sellmeier pole among them, are rejected.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum Dispersion {
+    /// n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    /// n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+impl Dispersion {
+    pub fn index(&self, wavelength: f32) -> f32 {
+        let micrometers = wavelength as f64 / 1000.0;
+        let l2 = micrometers * micrometers;
+        let index = match *self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt()
+            }
+        };
+        index as f32
+    }
+
+    /// A wavelength, in nanometers, at which the formula gives no usable index of refraction,
+    /// if there is one among the traced `wavelengths` or between the shortest and longest of them.
+    pub fn invalid_wavelength(&self, wavelengths: &[(f32, Color)]) -> Option<f32> {
+        let wavelengths = || wavelengths.iter().map(|w| w.0);
+        if let Dispersion::Sellmeier { b, c } = *self {
+            let shortest = wavelengths().fold(f32::INFINITY, f32::min) as f64;
+            let longest = wavelengths().fold(0.0, f32::max) as f64;
+            // Each term goes through infinity where λ² = cᵢ.
+            for i in 0..3 {
+                let pole = c[i].max(0.0).sqrt() * 1000.0;
+                if b[i] != 0.0 && shortest <= pole && pole <= longest {
+                    return Some(pole as f32);
+                }
+            }
+        }
+        wavelengths().find(|&w| {
+            let index = self.index(w);
+            !index.is_finite() || index <= 0.0
+        })
+    }
+}
+
+/// The CIE 1931 2° color matching functions, using the multi-lobe Gaussian fit from Wyman, Sloan
+/// and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions" (2013).
+pub fn cie_xyz(wavelength: f32) -> (f32, f32, f32) {
+    let lobe = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let sigma = if wavelength < mu { sigma_low } else { sigma_high };
+        let t = (wavelength - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    let x = 1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) -
+            0.065 * lobe(501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8);
+    (x, y, z)
+}
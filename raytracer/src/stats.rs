@@ -0,0 +1,188 @@
+use image::{DynamicImage, GenericImage};
+use progress::duration_secs;
+use sampling::heat_color;
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::time::Duration;
+use ViewBlock;
+
+/// The reason a ray was traced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    /// From the camera, once per sample and per wavelength in spectral mode.
+    Primary,
+    /// Towards a light, to find how much of its light reaches a point.
+    Shadow,
+    Reflection,
+    Refraction,
+    /// Carries on past a surface that lets some light through without bending it: the cut out
+    /// parts of surfaces, partly opaque ones, and whatever a shadow ray passes through.
+    Continuation,
+}
+
+/// Counts of the rays traced and of the work done to trace them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RayCounts {
+    pub primary: u64,
+    pub shadow: u64,
+    pub reflection: u64,
+    pub refraction: u64,
+    pub continuation: u64,
+    /// Tests of a ray against an element. The bounding boxes tested on the way are not counted.
+    pub intersection_tests: u64,
+    /// Number of rays shaded at each recursion depth, starting with the primary rays at depth 0.
+    pub depths: Vec<u64>,
+}
+impl RayCounts {
+    pub fn rays(&self) -> u64 {
+        self.primary + self.shadow + self.reflection + self.refraction + self.continuation
+    }
+
+    pub fn add(&mut self, other: &RayCounts) {
+        self.primary += other.primary;
+        self.shadow += other.shadow;
+        self.reflection += other.reflection;
+        self.refraction += other.refraction;
+        self.continuation += other.continuation;
+        self.intersection_tests += other.intersection_tests;
+        if self.depths.len() < other.depths.len() {
+            self.depths.resize(other.depths.len(), 0);
+        }
+        for (depth, &count) in self.depths.iter_mut().zip(other.depths.iter()) {
+            *depth += count;
+        }
+    }
+}
+
+thread_local!(static COUNTS: RefCell<RayCounts> = RefCell::new(RayCounts::default()));
+
+pub fn count_ray(kind: RayKind) {
+    COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        match kind {
+            RayKind::Primary => counts.primary += 1,
+            RayKind::Shadow => counts.shadow += 1,
+            RayKind::Reflection => counts.reflection += 1,
+            RayKind::Refraction => counts.refraction += 1,
+            RayKind::Continuation => counts.continuation += 1,
+        }
+    });
+}
+
+pub fn count_intersection_tests(tests: u64) {
+    COUNTS.with(|counts| counts.borrow_mut().intersection_tests += tests);
+}
+
+pub fn count_depth(depth: u32) {
+    COUNTS.with(|counts| {
+        let depths = &mut counts.borrow_mut().depths;
+        let depth = depth as usize;
+        if depths.len() <= depth {
+            depths.resize(depth + 1, 0);
+        }
+        depths[depth] += 1;
+    });
+}
+
+/// Runs `f`, returning what it returns along with the rays it traced on this thread.
+pub fn measure<T, F>(f: F) -> (T, RayCounts)
+    where F: FnOnce() -> T
+{
+    let outer = COUNTS.with(|counts| mem::take(&mut *counts.borrow_mut()));
+    let result = f();
+    let counts = COUNTS.with(|counts| mem::replace(&mut *counts.borrow_mut(), outer));
+    // Measurements may be nested, and the outer one includes the inner one.
+    COUNTS.with(|outer| outer.borrow_mut().add(&counts));
+    (result, counts)
+}
+
+/// The time taken and the rays traced to render a tile.
+#[derive(Debug, Clone)]
+pub struct TileStats {
+    pub tile: ViewBlock,
+    pub time: Duration,
+    pub counts: RayCounts,
+}
+
+/// Statistics gathered over the tiles of a render, as reported with its progress.
+#[derive(Debug, Clone, Default)]
+pub struct RenderStats {
+    pub counts: RayCounts,
+    pub tiles: Vec<TileStats>,
+}
+impl RenderStats {
+    pub fn add(&mut self, tile: &TileStats) {
+        self.counts.add(&tile.counts);
+        self.tiles.push(tile.clone());
+    }
+
+    pub fn time(&self) -> Duration {
+        self.tiles.iter().map(|t| t.time).sum()
+    }
+
+    pub fn slowest_tile(&self) -> Option<&TileStats> {
+        self.tiles.iter().max_by_key(|t| t.time)
+    }
+
+    /// Shows how long each tile of the block took to render, from black for no time at all
+    /// through red and yellow to white for the slowest. Tiles that were not rendered, such as
+    /// those resumed from a checkpoint, are black.
+    pub fn tile_heatmap(&self, block: &ViewBlock) -> DynamicImage {
+        let slowest = self.slowest_tile().map_or(0.0, |t| duration_secs(t.time));
+        let mut image = DynamicImage::new_rgb8(block.width, block.height);
+        for tile in &self.tiles {
+            let fraction = if slowest > 0.0 {
+                (duration_secs(tile.time) / slowest) as f32
+            } else {
+                0.0
+            };
+            let color = heat_color(fraction);
+            for y in 0..tile.tile.height {
+                for x in 0..tile.tile.width {
+                    image.put_pixel(tile.tile.x - block.x + x, tile.tile.y - block.y + y, color);
+                }
+            }
+        }
+        image
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = &self.counts;
+        let time = duration_secs(self.time());
+        writeln!(f, "Rendered {} tiles in {:.3}s", self.tiles.len(), time)?;
+        if let Some(slowest) = self.slowest_tile() {
+            writeln!(f,
+                     "  {:.1}ms per tile, slowest {:.1}ms at ({}, {})",
+                     time * 1e3 / self.tiles.len() as f64,
+                     duration_secs(slowest.time) * 1e3,
+                     slowest.tile.x,
+                     slowest.tile.y)?;
+        }
+        let rays = counts.rays();
+        writeln!(f, "Rays: {}", rays)?;
+        for &(name, count) in &[("primary", counts.primary),
+                                ("shadow", counts.shadow),
+                                ("reflection", counts.reflection),
+                                ("refraction", counts.refraction),
+                                ("continuation", counts.continuation)] {
+            writeln!(f, "  {:<12}{:>12} ({:.1}%)", name, count, percent(count, rays))?;
+        }
+        writeln!(f,
+                 "Intersection tests: {} ({:.1} per ray)",
+                 counts.intersection_tests,
+                 counts.intersection_tests as f64 / rays.max(1) as f64)?;
+        write!(f, "Rays shaded by recursion depth:")?;
+        let shaded: u64 = counts.depths.iter().sum();
+        for (depth, &count) in counts.depths.iter().enumerate() {
+            write!(f, "\n  {:<12}{:>12} ({:.1}%)", depth, count, percent(count, shaded))?;
+        }
+        Ok(())
+    }
+}
@@ -9,6 +9,10 @@ pub struct Vector3 {
     pub z: f64,
 }
 impl Vector3 {
+    pub fn new(x: f64, y: f64, z: f64) -> Vector3 {
+        Vector3 { x, y, z }
+    }
+
     pub fn zero() -> Vector3 {
         Vector3::from_one(0.0)
     }
@@ -0,0 +1,104 @@
+//! Checks the AOV passes against the beauty pass.
+
+extern crate raytracer;
+extern crate serde_json;
+
+use raytracer::{DEFAULT_TILE_SIZE, ViewBlock};
+use raytracer::aov::{render_aovs, render_aovs_with_samples};
+use raytracer::builder::SceneBuilder;
+use raytracer::checkpoint::Checkpoint;
+use raytracer::instance::TransformStep;
+use raytracer::point::Point;
+use raytracer::rendering::trace_pixel;
+use raytracer::sampling::AdaptiveSampling;
+use raytracer::scene::{Color, Element, Light, Material, Scene, Sphere};
+use raytracer::vector::Vector3;
+use std::fs::File;
+use std::path::Path;
+
+fn whole_image(scene: &Scene) -> ViewBlock {
+    ViewBlock {
+        x: 0,
+        y: 0,
+        width: scene.width,
+        height: scene.height,
+    }
+}
+
+fn scene() -> Scene {
+    let marble = Element::Sphere(Sphere {
+        center: Point::zero(),
+        radius: 0.5,
+        material: Material::diffuse(Color::rgb(0.2, 0.8, 0.3)),
+    });
+    SceneBuilder::new(32, 16)
+        .sphere(Point::new(-1.0, 0.0, -4.0), 1.0, Material::mirror(Color::gray(0.9), 0.6))
+        .plane(Point::new(0.0, -1.0, 0.0),
+               Vector3::new(0.0, 1.0, 0.0),
+               Material::diffuse(Color::gray(0.5)))
+        .group("marble", vec![marble])
+        .instance("marble", vec![TransformStep::Translate { x: 1.0, y: -0.5, z: -3.0 }])
+        .light(Light::directional(Vector3::new(-1.0, -1.0, -1.0), Color::gray(1.0), 3.0))
+        .build()
+        .unwrap()
+}
+
+fn load_scene(name: &str) -> Scene {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/scenes/{}.json", name));
+    serde_json::from_reader(File::open(path).unwrap()).unwrap()
+}
+
+fn check_lighting_passes(scene: &Scene) {
+    let aovs = render_aovs(&whole_image(scene), scene).unwrap();
+    for y in 0..scene.height {
+        for x in 0..scene.width {
+            let i = (y * scene.width + x) as usize;
+            let sum = aovs.direct[i] + aovs.indirect[i];
+            let beauty = trace_pixel(scene, x, y).unwrap();
+            assert!((sum.red - beauty.red).abs() < 1e-4, "pixel {}, {}", x, y);
+            assert!((sum.green - beauty.green).abs() < 1e-4, "pixel {}, {}", x, y);
+            assert!((sum.blue - beauty.blue).abs() < 1e-4, "pixel {}, {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn lighting_passes_add_up_to_beauty() {
+    check_lighting_passes(&scene());
+}
+
+#[test]
+fn lighting_passes_add_up_through_media_cutouts_and_wavelengths() {
+    for name in &["fog", "cutouts", "dispersion"] {
+        check_lighting_passes(&load_scene(name));
+    }
+}
+
+#[test]
+fn element_ids() {
+    let scene = scene();
+    let aovs = render_aovs(&whole_image(&scene), &scene).unwrap();
+    for &id in &[0, 1, 2] {
+        assert!(aovs.element.contains(&Some(id)), "no pixel of element {}", id);
+    }
+}
+
+#[test]
+fn adaptive_lighting_passes_add_up_to_beauty() {
+    let mut scene = scene();
+    scene.adaptive_sampling = Some(AdaptiveSampling {
+        threshold: 0.01,
+        min_samples: 2,
+        max_samples: 16,
+    });
+    let block = whole_image(&scene);
+    let mut checkpoint = Checkpoint::new(&block, DEFAULT_TILE_SIZE, &scene).unwrap();
+    raytracer::render_resumable(&block, &scene, &mut checkpoint, |_, _, _| {}).unwrap();
+    let aovs = render_aovs_with_samples(&block, &scene, &checkpoint.samples()).unwrap();
+    for (i, beauty) in checkpoint.colors().iter().enumerate() {
+        let sum = aovs.direct[i] + aovs.indirect[i];
+        assert!((sum.red - beauty.red).abs() < 1e-4, "pixel {}", i);
+        assert!((sum.green - beauty.green).abs() < 1e-4, "pixel {}", i);
+        assert!((sum.blue - beauty.blue).abs() < 1e-4, "pixel {}", i);
+    }
+}
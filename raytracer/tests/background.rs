@@ -0,0 +1,33 @@
+//! Checks the lights standing in for the light of the background.
+
+extern crate raytracer;
+
+use raytracer::background::{Background, Daylight, EnvironmentMap};
+use raytracer::scene::{Light, Texture};
+use raytracer::vector::Vector3;
+use std::path::PathBuf;
+
+fn from_above(background: &Background) -> bool {
+    background.lights().iter().all(|light| match *light {
+        Light::Directional(ref d) => d.direction.y < 0.0,
+        _ => false,
+    })
+}
+
+#[test]
+fn daylight_lights_from_above() {
+    let sky = Daylight::new(Vector3::new(0.6, 0.5, 0.4), 3.0, 1.0, 1.0, 8);
+    let background = Background::Daylight(sky);
+    assert!(!background.lights().is_empty());
+    assert!(from_above(&background));
+}
+
+#[test]
+fn environment_lights_from_below_on_request() {
+    let texture = || Texture::open(PathBuf::from("app/scenes/environment.png")).unwrap();
+    let above = Background::Environment(EnvironmentMap::new(texture(), 1.0, 8, false));
+    let everywhere = Background::Environment(EnvironmentMap::new(texture(), 1.0, 8, true));
+    assert!(from_above(&above));
+    assert_eq!(above.lights().len(), 16);
+    assert_eq!(everywhere.lights().len(), 32);
+}
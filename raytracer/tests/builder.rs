@@ -0,0 +1,79 @@
+//! Checks that scenes built in code render the same as their JSON serialization.
+
+extern crate raytracer;
+extern crate serde_json;
+
+use raytracer::ViewBlock;
+use raytracer::builder::SceneBuilder;
+use raytracer::error::RenderError;
+use raytracer::instance::TransformStep;
+use raytracer::point::Point;
+use raytracer::scene::{Color, Element, Light, Material, Scene, Sphere};
+use raytracer::vector::Vector3;
+
+fn render(scene: &Scene) -> Vec<u8> {
+    let block = ViewBlock {
+        x: 0,
+        y: 0,
+        width: scene.width,
+        height: scene.height,
+    };
+    raytracer::render(&block, scene).unwrap().raw_pixels()
+}
+
+#[test]
+fn round_trip() {
+    let marble = Element::Sphere(Sphere {
+        center: Point::zero(),
+        radius: 0.5,
+        material: Material::diffuse(Color::rgb(0.2, 0.8, 0.3)),
+    });
+    let scene = SceneBuilder::new(48, 32)
+        .perspective(60.0)
+        .sphere(Point::new(-1.0, 0.0, -5.0), 1.0, Material::mirror(Color::gray(0.9), 0.6))
+        .sphere(Point::new(1.2, 0.0, -4.0), 0.8, Material::glass(1.5))
+        .sphere(Point::new(0.0, 2.0, -8.0), 0.5, Material::emissive(Color::gray(1.0), 2.0))
+        .plane(Point::new(0.0, -1.0, 0.0),
+               Vector3::new(0.0, 2.0, 0.0),
+               Material::diffuse(Color::gray(0.5)).with_albedo(0.5))
+        .group("marble", vec![marble])
+        .instance("marble", vec![TransformStep::Translate { x: 0.0, y: -0.5, z: -3.0 }])
+        .light(Light::directional(Vector3::new(-1.0, -1.0, -1.0), Color::gray(1.0), 10.0))
+        .light(Light::point(Point::new(0.0, 3.0, -2.0), Color::rgb(1.0, 0.8, 0.6), 500.0))
+        .light(Light::spot(Point::new(2.0, 4.0, -4.0),
+                           Point::new(1.2, 0.0, -4.0),
+                           Color::gray(1.0),
+                           1000.0,
+                           10.0,
+                           20.0))
+        .build()
+        .unwrap();
+
+    let json = serde_json::to_string(&scene).unwrap();
+    let parsed: Scene = serde_json::from_str(&json).unwrap();
+    let pixels = render(&scene);
+    assert_eq!(render(&parsed), pixels);
+    // The bottom row only sees the floor.
+    let bottom = &pixels[48 * 31 * 3..];
+    assert!(bottom.chunks(3).all(|pixel| pixel != [0, 0, 0]));
+}
+
+#[test]
+fn plane_faces_up() {
+    let scene = SceneBuilder::new(16, 16)
+        .plane(Point::new(0.0, -1.0, 0.0),
+               Vector3::new(0.0, 1.0, 0.0),
+               Material::diffuse(Color::gray(0.5)))
+        .light(Light::directional(Vector3::new(0.0, -1.0, 0.0), Color::gray(1.0), 5.0))
+        .build()
+        .unwrap();
+    let pixels = render(&scene);
+    assert!(pixels[..16 * 8 * 3].iter().all(|&value| value == 0));
+    assert!(pixels[16 * 8 * 3..].iter().all(|&value| value > 0));
+}
+
+#[test]
+fn unknown_group() {
+    let result = SceneBuilder::new(16, 8).instance("missing", vec![]).build();
+    assert_eq!(result.err(), Some(RenderError::UnknownGroup("missing".to_string())));
+}
@@ -0,0 +1,84 @@
+//! Checks that the denoiser smooths noise without blurring the edges shown by the AOVs.
+
+extern crate raytracer;
+
+use raytracer::aov::Aovs;
+use raytracer::denoise::{denoise, DenoiseSettings};
+use raytracer::scene::Color;
+use raytracer::vector::Vector3;
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 16;
+
+/// Two flat halves meeting at a vertical edge, which shows up in both the normal and the albedo.
+fn guides() -> Aovs {
+    let pixels = (WIDTH * HEIGHT) as usize;
+    let left = |i: usize| (i as u32 % WIDTH) < WIDTH / 2;
+    Aovs {
+        width: WIDTH,
+        height: HEIGHT,
+        depth: vec![Some(5.0); pixels],
+        normal: (0..pixels)
+            .map(|i| if left(i) {
+                Vector3::new(0.0, 0.0, 1.0)
+            } else {
+                Vector3::new(1.0, 0.0, 0.0)
+            })
+            .collect(),
+        albedo: (0..pixels)
+            .map(|i| if left(i) { Color::gray(0.2) } else { Color::gray(0.8) })
+            .collect(),
+        element: (0..pixels).map(|i| Some(if left(i) { 0 } else { 1 })).collect(),
+        direct: vec![Color::gray(0.0); pixels],
+        indirect: vec![Color::gray(0.0); pixels],
+    }
+}
+
+/// The albedo of each pixel, lit evenly, with repeatable noise of up to 20% added.
+fn noisy_beauty(guides: &Aovs) -> Vec<Color> {
+    let mut state: u32 = 12345;
+    guides.albedo
+        .iter()
+        .map(|albedo| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let noise = (state >> 16) as f32 / 65536.0 * 0.4 - 0.2;
+            *albedo * (1.0 + noise)
+        })
+        .collect()
+}
+
+/// Mean and variance of the red channel over the columns in `columns`.
+fn statistics(colors: &[Color], columns: ::std::ops::Range<u32>) -> (f32, f32) {
+    let values: Vec<f32> = (0..HEIGHT)
+        .flat_map(|y| columns.clone().map(move |x| (y * WIDTH + x) as usize))
+        .map(|i| colors[i].red)
+        .collect();
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() /
+                   values.len() as f32;
+    (mean, variance)
+}
+
+#[test]
+fn smooths_noise() {
+    let guides = guides();
+    let beauty = noisy_beauty(&guides);
+    let denoised = denoise(&beauty, &guides, &DenoiseSettings::default()).unwrap();
+    for columns in [0..WIDTH / 2, WIDTH / 2..WIDTH] {
+        let before = statistics(&beauty, columns.clone());
+        let after = statistics(&denoised, columns.clone());
+        assert!(after.1 < before.1 * 0.5, "{:?}: {:?} -> {:?}", columns, before, after);
+    }
+}
+
+#[test]
+fn keeps_edges() {
+    let guides = guides();
+    let beauty = noisy_beauty(&guides);
+    let denoised = denoise(&beauty, &guides, &DenoiseSettings::default()).unwrap();
+    // The columns on either side of the edge keep the brightness of their own side.
+    let (left, _) = statistics(&denoised, WIDTH / 2 - 1..WIDTH / 2);
+    let (right, _) = statistics(&denoised, WIDTH / 2..WIDTH / 2 + 1);
+    assert!((left - 0.2).abs() < 0.02, "{}", left);
+    assert!((right - 0.8).abs() < 0.05, "{}", right);
+}
@@ -0,0 +1,43 @@
+//! Checks how emissive elements are seen from the points they light.
+
+extern crate raytracer;
+
+use raytracer::builder::SceneBuilder;
+use raytracer::emitter::{Emitter, EmitterShape};
+use raytracer::point::Point;
+use raytracer::scene::{Color, Material};
+use std::f64::consts::PI;
+
+fn sphere(radius: f64) -> Emitter {
+    Emitter {
+        shape: EmitterShape::Sphere {
+            center: Point::zero(),
+            radius,
+        },
+        radiance: Color::gray(1.0),
+        index: 0,
+        instanced: false,
+    }
+}
+
+#[test]
+fn sphere_solid_angle() {
+    // Close up, a sphere covers almost half of the view.
+    let near = sphere(1.0).view(&Point::new(0.0, 0.0, 1.0 + 1e-9)).unwrap();
+    assert!((near.solid_angle - 2.0 * PI).abs() < 1e-3, "{}", near.solid_angle);
+    // Far away, it covers about the area of its disc over the square of the distance.
+    let far = sphere(1.0).view(&Point::new(0.0, 0.0, 100.0)).unwrap();
+    assert!((far.solid_angle / (PI * 1e-4) - 1.0).abs() < 1e-3, "{}", far.solid_angle);
+    assert!(sphere(1.0).view(&Point::new(0.0, 0.0, 0.5)).is_none());
+}
+
+#[test]
+fn emitters_are_found_once() {
+    let scene = SceneBuilder::new(16, 8)
+        .sphere(Point::new(0.0, 0.0, -5.0), 1.0, Material::emissive(Color::gray(1.0), 2.0))
+        .sphere(Point::new(2.0, 0.0, -5.0), 1.0, Material::diffuse(Color::gray(0.5)))
+        .build()
+        .unwrap();
+    assert_eq!(scene.emitters().len(), 1);
+    assert!(std::ptr::eq(scene.emitters(), scene.emitters()));
+}
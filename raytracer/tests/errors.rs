@@ -0,0 +1,250 @@
+//! Checks that invalid scenes are reported as errors instead of aborting the render.
+
+extern crate image;
+extern crate raytracer;
+extern crate serde_json;
+
+use image::ImageBuffer;
+use raytracer::ViewBlock;
+use raytracer::builder::SceneBuilder;
+use raytracer::camera::Projection;
+use raytracer::checkpoint::Checkpoint;
+use raytracer::denoise::{denoise, DenoiseSettings};
+use raytracer::error::RenderError;
+use raytracer::instance::TransformStep;
+use raytracer::point::Point;
+use raytracer::rendering::BLACK;
+use raytracer::scene::{Element, LightProfile, Material, Scene, Sphere};
+use raytracer::spectral::SpectralSettings;
+use std::{env, fs, io, process};
+
+fn scene(width: u32, height: u32, elements: &str) -> Scene {
+    let json = format!(r#"{{"width": {}, "height": {}, "fov": 90.0, "elements": {},
+                          "lights": [], "shadow_bias": 1e-9, "max_recursion_depth": 4}}"#,
+                       width,
+                       height,
+                       elements);
+    serde_json::from_str(&json).unwrap()
+}
+
+fn whole_image(scene: &Scene) -> ViewBlock {
+    ViewBlock {
+        x: 0,
+        y: 0,
+        width: scene.width,
+        height: scene.height,
+    }
+}
+
+#[test]
+fn portrait_scene() {
+    let scene = scene(8, 16, "[]");
+    assert_eq!(raytracer::render(&whole_image(&scene), &scene).err(),
+               Some(RenderError::UnsupportedAspectRatio {
+                   width: 8,
+                   height: 16,
+               }));
+}
+
+#[test]
+fn field_of_view() {
+    let mut scene = scene(16, 8, "[]");
+    scene.fov = 270.0;
+    assert_eq!(raytracer::render(&whole_image(&scene), &scene).err(),
+               Some(RenderError::InvalidFieldOfView(270.0)));
+    scene.projection = Projection::Fisheye;
+    assert!(raytracer::render(&whole_image(&scene), &scene).is_ok());
+}
+
+#[test]
+fn unknown_group() {
+    let instance = r#"[{"Instance": {"group": "missing", "transform": {"steps": []}}}]"#;
+    let scene = scene(16, 8, instance);
+    assert_eq!(raytracer::render(&whole_image(&scene), &scene).err(),
+               Some(RenderError::UnknownGroup("missing".to_string())));
+}
+
+#[test]
+fn singular_transform() {
+    let build = |step: TransformStep| {
+        let marble = Element::Sphere(Sphere {
+            center: Point::zero(),
+            radius: 1.0,
+            material: Material::diffuse(BLACK),
+        });
+        SceneBuilder::new(16, 8)
+            .group("marble", vec![marble])
+            .instance("marble", vec![step])
+            .build()
+    };
+    let flattened = TransformStep::Scale { x: 1.0, y: 0.0, z: 1.0 };
+    let collapsed = TransformStep::Matrix([[1.0, 2.0, 0.0, 0.0],
+                                           [2.0, 4.0, 0.0, 0.0],
+                                           [0.0, 0.0, 1.0, 0.0],
+                                           [0.0, 0.0, 0.0, 1.0]]);
+    for step in [flattened, collapsed] {
+        assert_eq!(build(step).err(),
+                   Some(RenderError::SingularTransform("marble".to_string())));
+    }
+    assert!(build(TransformStep::Scale { x: 1e-3, y: 1e-3, z: 1e-3 }).is_ok());
+}
+
+#[test]
+fn overflowing_sphere() {
+    // The square of the radius overflows, so the sphere is hit at an infinite distance.
+    let sphere = r#"[{"Sphere": {"center": {"x": 0, "y": 0, "z": -5}, "radius": 1e200,
+                                 "material": {"coloration": {"Color": {"red": 1, "green": 1,
+                                                                       "blue": 1}},
+                                              "albedo": 0.18, "surface": "Diffuse"}}}]"#;
+    let scene = scene(16, 8, sphere);
+    match raytracer::render(&whole_image(&scene), &scene) {
+        Err(RenderError::NonFiniteDistance(_)) => {}
+        other => panic!("Expected a non-finite distance error, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn incompatible_checkpoint() {
+    let scene = scene(16, 8, "[]");
+    let mut checkpoint = Checkpoint::new(&ViewBlock {
+                                             x: 0,
+                                             y: 0,
+                                             width: 4,
+                                             height: 4,
+                                         },
+                                         4,
+                                         &scene)
+        .unwrap();
+    let result =
+        raytracer::render_resumable(&whole_image(&scene), &scene, &mut checkpoint, |_, _, _| {});
+    assert_eq!(result.err(), Some(RenderError::IncompatibleCheckpoint));
+}
+
+#[test]
+fn checkpoint_of_other_scene() {
+    let mut other = scene(16, 8, "[]");
+    other.fov = 60.0;
+    let scene = scene(16, 8, "[]");
+    let mut checkpoint = Checkpoint::new(&whole_image(&scene), 4, &other).unwrap();
+    let result =
+        raytracer::render_resumable(&whole_image(&scene), &scene, &mut checkpoint, |_, _, _| {});
+    assert_eq!(result.err(), Some(RenderError::IncompatibleCheckpoint));
+}
+
+#[test]
+fn corrupt_checkpoint() {
+    let path = env::temp_dir().join(format!("corrupt-{}.checkpoint", process::id()));
+    let mut bytes = b"RTCK".to_vec();
+    // Version, scene fingerprint, a 16x8 block in tiles of 4, and one tile far outside it.
+    for value in &[1, 0, 0, 0, 0, 16, 8, 4, 1, 0, 0, 65536, 65536] {
+        bytes.extend_from_slice(&(*value as u32).to_le_bytes());
+    }
+    fs::write(&path, &bytes).unwrap();
+    let result = Checkpoint::load(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn checkpoint_tile_off_grid() {
+    let path = env::temp_dir().join(format!("off-grid-{}.checkpoint", process::id()));
+    let mut bytes = b"RTCK".to_vec();
+    // Version, scene fingerprint, a 16x8 block in tiles of 4, and a 1x1 tile in the corner.
+    for value in &[1, 0, 0, 0, 0, 16, 8, 4, 1, 0, 0, 1, 1, 1, 0, 0, 0] {
+        bytes.extend_from_slice(&(*value as u32).to_le_bytes());
+    }
+    fs::write(&path, &bytes).unwrap();
+    let result = Checkpoint::load(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn checkpoint_larger_than_file() {
+    let path = env::temp_dir().join(format!("truncated-{}.checkpoint", process::id()));
+    let mut bytes = b"RTCK".to_vec();
+    // A block of 32768x32768 in a single tile, which the file ends before.
+    for value in &[1, 0, 0, 0, 0, 32768, 32768, 32768, 1, 0, 0, 32768, 32768] {
+        bytes.extend_from_slice(&(*value as u32).to_le_bytes());
+    }
+    fs::write(&path, &bytes).unwrap();
+    let result = Checkpoint::load(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::UnexpectedEof));
+}
+
+#[test]
+fn sellmeier_pole() {
+    // The pole at 0.25 μm² is at 500 nm, between the traced wavelengths.
+    let glass = r#"[{"Sphere": {"center": {"x": 0, "y": 0, "z": -5}, "radius": 1,
+                                "material": {"coloration": {"Color": {"red": 1, "green": 1,
+                                                                      "blue": 1}},
+                                             "albedo": 0.18,
+                                             "surface": {"Refractive": {
+                                                 "index": 1.5, "transparency": 1,
+                                                 "dispersion": {"Sellmeier": {
+                                                     "b": [1, 0, 0],
+                                                     "c": [0.25, 0, 0]}}}}}}}]"#;
+    let mut scene = scene(16, 8, glass);
+    scene.spectral = Some(SpectralSettings { samples: 8 });
+    assert_eq!(raytracer::render(&whole_image(&scene), &scene).err(),
+               Some(RenderError::InvalidDispersion(500.0)));
+}
+
+#[test]
+fn empty_image() {
+    let scene = scene(0, 0, "[]");
+    assert_eq!(raytracer::render(&whole_image(&scene), &scene).err(),
+               Some(RenderError::EmptyImage {
+                   width: 0,
+                   height: 0,
+               }));
+}
+
+#[test]
+fn denoise_size_mismatch() {
+    let scene = scene(16, 8, "[]");
+    let aovs = raytracer::aov::render_aovs(&whole_image(&scene), &scene).unwrap();
+    let beauty = vec![BLACK; 4];
+    assert_eq!(denoise(&beauty, &aovs, &DenoiseSettings::default()).err(),
+               Some(RenderError::SizeMismatch {
+                   expected: 128,
+                   actual: 4,
+               }));
+}
+
+#[test]
+fn image_too_small() {
+    let scene = scene(100, 10, "[]");
+    let mut pixels = vec![0; 10 * 100 * 4];
+    let mut image = ImageBuffer::from_raw(10, 100, &mut pixels[..]).unwrap();
+    assert_eq!(raytracer::render_into(&whole_image(&scene), &scene, &mut image).err(),
+               Some(RenderError::ImageTooSmall {
+                   block: (100, 10),
+                   image: (10, 100),
+               }));
+}
+
+#[test]
+fn nan_light_profile() {
+    let path = env::temp_dir().join(format!("nan-{}.profile", process::id()));
+    fs::write(&path, "0 1.0\nNaN 0.5\n").unwrap();
+    let result = LightProfile::open(path.clone());
+    fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+}
+
+#[test]
+fn invalid_opacity() {
+    let sphere = r#"[{"Sphere": {"center": {"x": 0, "y": 0, "z": -5}, "radius": 1,
+                                 "material": {"coloration": {"Color": {"red": 1, "green": 1,
+                                                                       "blue": 1}},
+                                              "albedo": 0.18, "surface": "Diffuse",
+                                              "opacity": 1.5}}}]"#;
+    let mut scene = scene(16, 8, sphere);
+    assert_eq!(raytracer::render(&whole_image(&scene), &scene).err(),
+               Some(RenderError::InvalidOpacity(1.5)));
+    scene.elements[0].material_mut().unwrap().opacity = f32::NAN;
+    assert!(matches!(raytracer::render(&whole_image(&scene), &scene),
+                     Err(RenderError::InvalidOpacity(o)) if o.is_nan()));
+}
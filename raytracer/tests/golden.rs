@@ -0,0 +1,214 @@
+//! Renders the scenes in `tests/scenes` and compares them with the reference images in
+//! `tests/golden`, so that changes to the renderer do not silently alter its output.
+//!
+//! Run with `UPDATE_GOLDEN=1` to overwrite the reference images after an intended change.
+
+extern crate image;
+extern crate raytracer;
+extern crate serde_json;
+
+use image::{DynamicImage, GenericImage, ImageFormat};
+use raytracer::ViewBlock;
+use raytracer::scene::Scene;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Largest acceptable average color difference over the whole image, in CIE76 ΔE units. A
+/// difference of about 2.3 is just noticeable.
+const MAX_MEAN_DELTA_E: f64 = 1.0;
+
+/// Pixels differing by more than this are counted as visibly wrong.
+const VISIBLE_DELTA_E: f64 = 10.0;
+
+/// Largest acceptable fraction of visibly wrong pixels, which allows for a few edge pixels to
+/// flip between objects after harmless numeric changes.
+const MAX_VISIBLE_FRACTION: f64 = 0.005;
+
+fn test_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(path)
+}
+
+fn render_scene(name: &str) -> DynamicImage {
+    let file = File::open(test_path(&format!("scenes/{}.json", name))).unwrap();
+    let scene: Scene = serde_json::from_reader(file).unwrap();
+    let block = ViewBlock {
+        x: 0,
+        y: 0,
+        width: scene.width,
+        height: scene.height,
+    };
+    raytracer::render(&block, &scene).unwrap()
+}
+
+fn save_image(image: &DynamicImage, path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut file = File::create(path).unwrap();
+    image.save(&mut file, ImageFormat::PNG).unwrap();
+}
+
+/// Converts an 8-bit sRGB color to CIELAB, under the D65 white point.
+fn to_lab(rgb: [u8; 3]) -> [f64; 3] {
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(rgb[0]), linear(rgb[1]), linear(rgb[2]));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f64| if t > 216.0 / 24389.0 {
+        t.cbrt()
+    } else {
+        (24389.0 / 27.0 * t + 16.0) / 116.0
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn delta_e(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let (a, b) = (to_lab(a), to_lab(b));
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn check_golden(name: &str) {
+    let actual = render_scene(name);
+    let golden_path = test_path(&format!("golden/{}.png", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        save_image(&actual, &golden_path);
+        return;
+    }
+
+    let expected = image::open(&golden_path)
+        .unwrap_or_else(|e| {
+            panic!("Cannot open {} ({}); run with UPDATE_GOLDEN=1 to create it",
+                   golden_path.display(),
+                   e)
+        });
+    assert_eq!(actual.dimensions(), expected.dimensions(), "{}: size mismatch", name);
+
+    let (actual_rgb, expected_rgb) = (actual.to_rgb(), expected.to_rgb());
+    let differences: Vec<f64> = actual_rgb.pixels()
+        .zip(expected_rgb.pixels())
+        .map(|(a, e)| delta_e(a.data, e.data))
+        .collect();
+    let mean = differences.iter().sum::<f64>() / differences.len() as f64;
+    let visible = differences.iter().filter(|&&d| d > VISIBLE_DELTA_E).count() as f64 /
+                  differences.len() as f64;
+    if mean > MAX_MEAN_DELTA_E || visible > MAX_VISIBLE_FRACTION {
+        let failure_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("target/golden")
+            .join(format!("{}.png", name));
+        save_image(&actual, &failure_path);
+        panic!("{}: differs from the golden image (mean ΔE {:.3}, {:.2}% of pixels visibly \
+                different); the render was saved to {}",
+               name,
+               mean,
+               visible * 100.0,
+               failure_path.display());
+    }
+}
+
+#[test]
+fn basic() {
+    check_golden("basic");
+}
+
+#[test]
+fn spot() {
+    check_golden("spot");
+}
+
+#[test]
+fn emissive() {
+    check_golden("emissive");
+}
+
+#[test]
+fn fog() {
+    check_golden("fog");
+}
+
+#[test]
+fn dispersion() {
+    check_golden("dispersion");
+}
+
+#[test]
+fn instances() {
+    check_golden("instances");
+}
+
+#[test]
+fn sdf() {
+    check_golden("sdf");
+}
+
+#[test]
+fn caustics() {
+    check_golden("caustics");
+}
+
+#[test]
+fn caustics_transmitted() {
+    check_golden("caustics_transmitted");
+}
+
+#[test]
+fn fisheye() {
+    check_golden("fisheye");
+}
+
+#[test]
+fn panorama() {
+    check_golden("panorama");
+}
+
+#[test]
+fn adaptive() {
+    check_golden("adaptive");
+}
+
+#[test]
+fn many_lights() {
+    check_golden("many_lights");
+}
+
+#[test]
+fn many_lights_table() {
+    check_golden("many_lights_table");
+}
+
+#[test]
+fn metals() {
+    check_golden("metals");
+}
+
+#[test]
+fn cutouts() {
+    check_golden("cutouts");
+}
+
+#[test]
+fn background_gradient() {
+    check_golden("background_gradient");
+}
+
+#[test]
+fn background_environment() {
+    check_golden("background_environment");
+}
+
+#[test]
+fn background_daylight() {
+    check_golden("background_daylight");
+}
+
+#[test]
+fn emissive_shapes() {
+    check_golden("emissive_shapes");
+}
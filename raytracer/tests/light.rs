@@ -0,0 +1,94 @@
+//! Checks how spot lights fall off across their cone and along their light profiles.
+
+extern crate raytracer;
+
+use raytracer::point::Point;
+use raytracer::scene::{Color, Light, LightProfile, SpotLight};
+use std::path::PathBuf;
+
+/// A spot light at the origin shining down -z, between cone angles of 10 and 20 degrees.
+fn spot(profile: Option<LightProfile>) -> SpotLight {
+    let light = Light::spot(Point::zero(),
+                            Point::new(0.0, 0.0, -1.0),
+                            Color::gray(1.0),
+                            1.0,
+                            10.0,
+                            20.0);
+    match light {
+        Light::Spot(mut spot) => {
+            spot.profile = profile;
+            spot
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// A point one unit down the axis, `degrees` away from it.
+fn at_angle(degrees: f64) -> Point {
+    let angle = degrees.to_radians();
+    Point::new(angle.sin(), 0.0, -angle.cos())
+}
+
+fn profile(samples: Vec<(f64, f32)>) -> LightProfile {
+    LightProfile::new(PathBuf::from("test.profile"), samples).unwrap()
+}
+
+#[test]
+fn spot_falloff() {
+    let spot = spot(None);
+    for degrees in [0.0, 5.0, 10.0] {
+        assert_eq!(spot.falloff(&at_angle(degrees)), 1.0, "{}", degrees);
+    }
+    for degrees in [20.0, 45.0, 180.0] {
+        assert_eq!(spot.falloff(&at_angle(degrees)), 0.0, "{}", degrees);
+    }
+    // Between the cones the falloff is a smoothstep in the cosine of the angle.
+    let (inner, outer) = (10f64.to_radians().cos(), 20f64.to_radians().cos());
+    let t = (15f64.to_radians().cos() - outer) / (inner - outer);
+    let expected = (t * t * (3.0 - 2.0 * t)) as f32;
+    assert!((spot.falloff(&at_angle(15.0)) - expected).abs() < 1e-5);
+    let mut previous = 1.0;
+    for tenth in 101..200 {
+        let falloff = spot.falloff(&at_angle(tenth as f64 / 10.0));
+        assert!(falloff <= previous, "{}", tenth);
+        previous = falloff;
+    }
+}
+
+#[test]
+fn profile_interpolation() {
+    // The entries are sorted, so they may be given in any order.
+    let profile = profile(vec![(10.0, 0.5), (0.0, 1.0), (30.0, 0.0)]);
+    let cases = [(-5.0, 1.0), (0.0, 1.0), (5.0, 0.75), (10.0, 0.5), (20.0, 0.25), (30.0, 0.0),
+                 (90.0, 0.0)];
+    for (angle, expected) in cases {
+        let multiplier = profile.multiplier(angle);
+        assert!((multiplier - expected).abs() < 1e-6, "{}: {}", angle, multiplier);
+    }
+    assert_eq!(profile.multiplier(f64::NAN), 0.0);
+}
+
+#[test]
+fn single_entry_profile() {
+    let profile = profile(vec![(15.0, 0.4)]);
+    for angle in [0.0, 15.0, 60.0] {
+        assert_eq!(profile.multiplier(angle), 0.4);
+    }
+}
+
+#[test]
+fn profiled_spot() {
+    let spot = spot(Some(profile(vec![(0.0, 1.0), (10.0, 0.5)])));
+    assert_eq!(spot.falloff(&at_angle(0.0)), 1.0);
+    assert!((spot.falloff(&at_angle(5.0)) - 0.75).abs() < 1e-4);
+    // The profile scales the cone, so the light still ends at the outer angle.
+    assert_eq!(spot.falloff(&at_angle(25.0)), 0.0);
+}
+
+#[test]
+fn invalid_profiles() {
+    let path = PathBuf::from("test.profile");
+    assert!(LightProfile::new(path.clone(), vec![]).is_err());
+    assert!(LightProfile::new(path.clone(), vec![(f64::NAN, 1.0)]).is_err());
+    assert!(LightProfile::new(path, vec![(0.0, f32::NAN)]).is_err());
+}
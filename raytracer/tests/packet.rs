@@ -0,0 +1,100 @@
+//! Checks that tracing primary rays in packets finds the same hits as tracing them one at a time.
+
+extern crate raytracer;
+extern crate serde_json;
+
+use raytracer::packet::{PACKET_SIZE, RayPacket};
+use raytracer::rendering::Ray;
+use raytracer::scene::Scene;
+use std::fs::File;
+use std::path::Path;
+use std::ptr;
+
+fn load_scene(name: &str) -> Scene {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/scenes/{}.json", name));
+    serde_json::from_reader(File::open(path).unwrap()).unwrap()
+}
+
+/// Compares every lane of the packets of 2x2 pixels covering the scene with the scalar trace of
+/// the same pixel.
+fn check_packets(name: &str) {
+    let scene = load_scene(name);
+    let mut hits = 0;
+    for y in (0..scene.height).step_by(2) {
+        for x in (0..scene.width).step_by(2) {
+            let quad = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)];
+            let packet = RayPacket::create_prime(&quad, &scene);
+            let packet_hits = scene.trace_packet(&packet).unwrap();
+            for (lane, &(x, y)) in quad.iter().enumerate() {
+                let ray = Ray::create_prime(x, y, &scene);
+                assert_eq!(packet.active[lane], ray.is_some(), "{} ({}, {})", name, x, y);
+                let ray = match ray {
+                    Some(ray) => ray,
+                    None => continue,
+                };
+                let scalar = scene.trace(&ray).unwrap();
+                match (packet_hits[lane].as_ref(), scalar.as_ref()) {
+                    (Some(a), Some(b)) => {
+                        assert!(ptr::eq(a.element, b.element), "{} ({}, {})", name, x, y);
+                        assert_eq!(a.index, b.index);
+                        assert!((a.distance - b.distance).abs() <= 1e-9 * b.distance,
+                                "{} ({}, {}): {} != {}",
+                                name,
+                                x,
+                                y,
+                                a.distance,
+                                b.distance);
+                        let hit = ray.origin + (ray.direction * b.distance);
+                        let normals = (a.surface_normal(&hit), b.surface_normal(&hit));
+                        assert!((normals.0 - normals.1).length() < 1e-9,
+                                "{} ({}, {})",
+                                name,
+                                x,
+                                y);
+                        hits += 1;
+                    }
+                    (None, None) => {}
+                    _ => panic!("{} ({}, {}): packet and scalar traces disagree", name, x, y),
+                }
+            }
+        }
+    }
+    assert!(hits > 0, "{}: nothing was hit", name);
+}
+
+#[test]
+fn spheres_and_planes() {
+    check_packets("basic");
+}
+
+#[test]
+fn triangles() {
+    check_packets("emissive_shapes");
+}
+
+#[test]
+fn sdfs() {
+    check_packets("sdf");
+}
+
+#[test]
+fn instances() {
+    check_packets("instances");
+}
+
+#[test]
+fn cut_out_lanes() {
+    check_packets("cutouts");
+}
+
+#[test]
+fn partial_packets() {
+    let scene = load_scene("basic");
+    for count in 0..PACKET_SIZE + 2 {
+        let pixels = vec![(10, 10); count];
+        let packet = RayPacket::create_prime(&pixels, &scene);
+        let active = packet.active.iter().filter(|&&a| a).count();
+        assert_eq!(active, count.min(PACKET_SIZE));
+        scene.trace_packet(&packet).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Checks tiled rendering, the progress it reports, and resuming it from a checkpoint.
+
+extern crate raytracer;
+
+use raytracer::ViewBlock;
+use raytracer::builder::SceneBuilder;
+use raytracer::checkpoint::Checkpoint;
+use raytracer::point::Point;
+use raytracer::progress::Progress;
+use raytracer::scene::{Color, Light, Material, Scene};
+use raytracer::stats::{RayCounts, TileStats};
+use raytracer::vector::Vector3;
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+fn scene() -> Scene {
+    SceneBuilder::new(32, 16)
+        .sphere(Point::new(0.0, 0.0, -3.0), 1.0, Material::mirror(Color::gray(0.9), 0.5))
+        .plane(Point::new(0.0, -1.0, 0.0),
+               Vector3::new(0.0, 1.0, 0.0),
+               Material::diffuse(Color::gray(0.5)))
+        .light(Light::directional(Vector3::new(0.0, -1.0, -1.0), Color::gray(1.0), 5.0))
+        .build()
+        .unwrap()
+}
+
+fn whole_image(scene: &Scene) -> ViewBlock {
+    ViewBlock {
+        x: 0,
+        y: 0,
+        width: scene.width,
+        height: scene.height,
+    }
+}
+
+fn progress(completed_tiles: u32, resumed_tiles: u32, elapsed: Duration) -> Progress {
+    let tile = ViewBlock {
+        x: 0,
+        y: 0,
+        width: 1,
+        height: 1,
+    };
+    Progress {
+        completed_tiles,
+        resumed_tiles,
+        total_tiles: 5,
+        elapsed,
+        tile,
+        stats: TileStats {
+            tile,
+            time: Duration::from_secs(1),
+            counts: RayCounts::default(),
+        },
+    }
+}
+
+#[test]
+fn tiles_cover_block() {
+    let block = ViewBlock {
+        x: 3,
+        y: 2,
+        width: 10,
+        height: 7,
+    };
+    let tiles = raytracer::tiles(&block, 4);
+    assert_eq!(tiles.len(), 6);
+    let mut covered = vec![0; 10 * 7];
+    for tile in &tiles {
+        assert!(tile.width <= 4 && tile.height <= 4);
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                covered[((y - block.y) * block.width + x - block.x) as usize] += 1;
+            }
+        }
+    }
+    assert!(covered.iter().all(|&count| count == 1));
+}
+
+#[test]
+fn reports_every_tile() {
+    let scene = scene();
+    let block = whole_image(&scene);
+    let mut reports = vec![];
+    let image = raytracer::render_with_progress(&block, &scene, 8, |progress, _| {
+            reports.push(progress.clone())
+        })
+        .unwrap();
+    assert_eq!(reports.len(), 8);
+    for (i, progress) in reports.iter().enumerate() {
+        assert_eq!(progress.completed_tiles, i as u32 + 1);
+        assert_eq!(progress.total_tiles, 8);
+        assert_eq!(progress.resumed_tiles, 0);
+        assert!(progress.eta().is_some());
+    }
+    let last = reports.last().unwrap();
+    assert!(last.is_done());
+    assert_eq!(last.fraction(), 1.0);
+    assert_eq!(last.eta(), Some(Duration::from_secs(0)));
+    assert_eq!(image.raw_pixels(),
+               raytracer::render(&block, &scene).unwrap().raw_pixels());
+}
+
+#[test]
+fn eta() {
+    // One tile rendered in 10 seconds after one was resumed leaves 3 to go.
+    assert_eq!(progress(2, 1, Duration::from_secs(10)).eta(),
+               Some(Duration::from_secs(30)));
+    assert_eq!(progress(1, 1, Duration::from_secs(10)).eta(), None);
+    assert_eq!(progress(2, 1, Duration::from_secs(10)).fraction(), 0.4);
+}
+
+#[test]
+fn resume_from_saved_checkpoint() {
+    let scene = scene();
+    let block = whole_image(&scene);
+    let path = env::temp_dir().join(format!("resume-{}.checkpoint", process::id()));
+    let mut checkpoint = Checkpoint::new(&block, 8, &scene).unwrap();
+    let uninterrupted = raytracer::render_resumable(&block, &scene, &mut checkpoint, |p, _, c| {
+            if p.completed_tiles == 3 {
+                c.save(&path).unwrap();
+            }
+        })
+        .unwrap();
+
+    let mut checkpoint = Checkpoint::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(checkpoint.tiles().len(), 3);
+    let mut resumed_tiles = vec![];
+    let resumed = raytracer::render_resumable(&block, &scene, &mut checkpoint, |p, _, _| {
+            resumed_tiles.push(p.resumed_tiles)
+        })
+        .unwrap();
+    assert_eq!(resumed_tiles, vec![3; 5]);
+    assert_eq!(resumed.raw_pixels(), uninterrupted.raw_pixels());
+}
@@ -0,0 +1,43 @@
+//! Checks where adaptive sampling spends its samples.
+
+extern crate raytracer;
+
+use raytracer::{DEFAULT_TILE_SIZE, ViewBlock};
+use raytracer::builder::SceneBuilder;
+use raytracer::checkpoint::Checkpoint;
+use raytracer::point::Point;
+use raytracer::sampling::AdaptiveSampling;
+use raytracer::scene::{Color, Light, Material};
+use raytracer::vector::Vector3;
+
+#[test]
+fn samples_follow_edges() {
+    let settings = AdaptiveSampling {
+        threshold: 0.004,
+        min_samples: 4,
+        max_samples: 64,
+    };
+    let scene = SceneBuilder::new(32, 16)
+        .sphere(Point::new(0.0, 0.0, -3.0), 1.0, Material::diffuse(Color::gray(0.8)))
+        .light(Light::directional(Vector3::new(0.0, 0.0, -1.0), Color::gray(1.0), 2.0))
+        .adaptive_sampling(settings)
+        .build()
+        .unwrap();
+    let block = ViewBlock {
+        x: 0,
+        y: 0,
+        width: scene.width,
+        height: scene.height,
+    };
+    let mut checkpoint = Checkpoint::new(&block, DEFAULT_TILE_SIZE, &scene).unwrap();
+    raytracer::render_resumable(&block, &scene, &mut checkpoint, |_, _, _| {}).unwrap();
+    let samples = checkpoint.samples();
+
+    // The corners only see the background, which is flat.
+    for &(x, y) in &[(0, 0), (31, 0), (0, 15), (31, 15)] {
+        assert_eq!(samples[y * 32 + x], 4, "pixel {}, {}", x, y);
+    }
+    // Pixels on the outline of the sphere are partly covered, so their samples disagree.
+    let row = &samples[8 * 32..9 * 32];
+    assert!(row.iter().any(|&s| s > 4), "no pixel of the outline was refined: {:?}", row);
+}
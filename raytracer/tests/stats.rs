@@ -0,0 +1,85 @@
+//! Checks the statistics reported with the progress of a render.
+
+extern crate raytracer;
+extern crate serde_json;
+
+use raytracer::ViewBlock;
+use raytracer::builder::SceneBuilder;
+use raytracer::point::Point;
+use raytracer::scene::{Color, Light, Material, Scene};
+use raytracer::stats::RenderStats;
+use raytracer::vector::Vector3;
+use std::fs::File;
+use std::path::Path;
+
+fn render_stats(scene: &Scene, tile_size: u32) -> RenderStats {
+    let block = ViewBlock {
+        x: 0,
+        y: 0,
+        width: scene.width,
+        height: scene.height,
+    };
+    let mut stats = RenderStats::default();
+    raytracer::render_with_progress(&block, scene, tile_size, |progress, _| {
+            stats.add(&progress.stats)
+        })
+        .unwrap();
+    stats
+}
+
+fn mirror_scene() -> Scene {
+    SceneBuilder::new(32, 16)
+        .sphere(Point::new(0.0, 0.0, -3.0), 1.0, Material::mirror(Color::gray(0.9), 0.5))
+        .plane(Point::new(0.0, -1.0, 0.0),
+               Vector3::new(0.0, 1.0, 0.0),
+               Material::diffuse(Color::gray(0.5)))
+        .light(Light::directional(Vector3::new(0.0, -1.0, -1.0), Color::gray(1.0), 5.0))
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn counts_rays() {
+    let stats = render_stats(&mirror_scene(), 8);
+    let counts = &stats.counts;
+    assert_eq!(stats.tiles.len(), 8);
+    assert_eq!(counts.primary, 32 * 16);
+    assert_eq!(counts.depths[0], counts.primary);
+    assert!(counts.reflection > 0);
+    assert_eq!(counts.depths[1], counts.reflection);
+    assert!(counts.shadow > 0);
+    assert_eq!(counts.refraction, 0);
+    assert_eq!(counts.continuation, 0);
+    // Every primary ray is tested against both elements.
+    assert!(counts.intersection_tests >= 2 * counts.primary);
+}
+
+#[test]
+fn counts_continuations() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenes/cutouts.json");
+    let scene: Scene = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+    let counts = render_stats(&scene, 16).counts;
+    assert!(counts.continuation > 0);
+    assert!(counts.intersection_tests >= counts.primary + counts.continuation);
+}
+
+#[test]
+fn repeatable() {
+    let scene = mirror_scene();
+    let first = render_stats(&scene, 8);
+    let second = render_stats(&scene, 16);
+    assert_eq!(first.counts, second.counts);
+    assert_eq!(second.tiles.len(), 2);
+}
+
+#[test]
+fn tile_heatmap() {
+    let block = ViewBlock {
+        x: 0,
+        y: 0,
+        width: 32,
+        height: 16,
+    };
+    let heatmap = render_stats(&mirror_scene(), 8).tile_heatmap(&block);
+    assert_eq!(heatmap.raw_pixels().len(), 32 * 16 * 3);
+}